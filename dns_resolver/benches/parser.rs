@@ -0,0 +1,42 @@
+//! Run with `cargo bench -p dns_resolver`. The packets are the real responses from the fuzz
+//! corpus, so both stay representative of what we actually parse.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dns_resolver::{bench_decode_name, dns_packet::DNSPacket, encode_dns_name_into};
+
+const A_ANSWER: &[u8] = include_bytes!("../fuzz/corpus/parse_packet/a_answer.bin");
+const CNAME_WITH_OPT: &[u8] = include_bytes!("../fuzz/corpus/parse_packet/cname_with_opt.bin");
+const REFERRAL_WITH_GLUE: &[u8] =
+    include_bytes!("../fuzz/corpus/parse_packet/referral_with_glue.bin");
+
+fn parse_packet(c: &mut Criterion) {
+    for (name, packet) in [
+        ("a_answer", A_ANSWER),
+        ("cname_with_opt", CNAME_WITH_OPT),
+        ("referral_with_glue", REFERRAL_WITH_GLUE),
+    ] {
+        c.bench_function(&format!("parse {}", name), |b| {
+            b.iter(|| DNSPacket::try_from(black_box(packet)).unwrap())
+        });
+    }
+}
+
+fn name_codec(c: &mut Criterion) {
+    // The question name, and the second NS name which is "b" followed by a pointer into the first
+    c.bench_function("decode uncompressed name", |b| {
+        b.iter(|| bench_decode_name(black_box(REFERRAL_WITH_GLUE), 12).unwrap())
+    });
+    c.bench_function("decode compressed name", |b| {
+        b.iter(|| bench_decode_name(black_box(REFERRAL_WITH_GLUE), 0x49).unwrap())
+    });
+    c.bench_function("encode name", |b| {
+        let mut buf = Vec::with_capacity(64);
+        b.iter(|| {
+            buf.clear();
+            encode_dns_name_into(black_box("a.gtld-servers.net"), &mut buf).unwrap();
+        })
+    });
+}
+
+criterion_group!(benches, parse_packet, name_codec);
+criterion_main!(benches);
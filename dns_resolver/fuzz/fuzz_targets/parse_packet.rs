@@ -0,0 +1,11 @@
+//! Run with `cargo +nightly fuzz run parse_packet` from the dns_resolver directory.
+//! The seeds in corpus/parse_packet are real responses to start mutating from.
+#![no_main]
+
+use dns_resolver::dns_packet::DNSPacket;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Errors are fine, panics are what we're after
+    let _ = DNSPacket::try_from(data);
+});
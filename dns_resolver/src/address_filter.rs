@@ -0,0 +1,82 @@
+use std::{fmt, net::IpAddr};
+
+/// Decides which addresses a lookup may return, see `Resolver::address_filter`.
+pub struct AddressFilter {
+    allows: Box<dyn Fn(IpAddr) -> bool + Send + Sync>,
+}
+
+impl AddressFilter {
+    /// A filter allowing the addresses `allows` returns true for.
+    pub fn new(allows: impl Fn(IpAddr) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            allows: Box::new(allows),
+        }
+    }
+
+    /// Protection against DNS rebinding: only addresses on the public internet are allowed, so a
+    /// name can't be pointed at loopback, private networks or link-local addresses.
+    pub fn rebinding_protection() -> Self {
+        Self::new(|ip| !is_internal(ip))
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        (self.allows)(ip)
+    }
+}
+
+impl fmt::Debug for AddressFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AddressFilter").finish_non_exhaustive()
+    }
+}
+
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+        }
+        // An IPv4 address in IPv6 clothing reaches the same host
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn test_rebinding_protection_rejects_internal_addresses() {
+        let filter = AddressFilter::rebinding_protection();
+
+        for internal in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!filter.allows(internal.parse().unwrap()), "{}", internal);
+        }
+        assert!(filter.allows(Ipv4Addr::new(93, 184, 216, 34).into()));
+        assert!(filter.allows("2606:2800:220:1::1".parse::<Ipv6Addr>().unwrap().into()));
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+#[cfg(feature = "serde")]
+use std::{error::Error, fs, path::Path};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "serde")]
+use crate::encode_dns_name;
+use crate::{dns_record::DNSRecord, record_type::RecordType};
+
+#[derive(Debug, Clone)]
+pub struct CachedEntry {
+    records: Vec<DNSRecord>,
+    expires_at: Instant,
+}
+
+impl CachedEntry {
+    pub fn records(&self) -> &[DNSRecord] {
+        &self.records
+    }
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at
+    }
+    /// How long until the entry expires, zero once it has.
+    pub fn remaining_ttl(&self) -> Duration {
+        self.expires_at.saturating_duration_since(Instant::now())
+    }
+}
+
+// What an entry looks like on disk. Expiry is stored as the TTL that was left at save time,
+// since an Instant means nothing after a restart.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SavedEntry {
+    name: String,
+    record_type: u16,
+    remaining_ttl: u64,
+    records: Vec<SavedRecord>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SavedRecord {
+    name: String,
+    record_type: u16,
+    class: u16,
+    ttl: u32,
+    rdata: Vec<u8>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<&DNSRecord> for SavedRecord {
+    type Error = Box<dyn Error>;
+
+    fn try_from(record: &DNSRecord) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: String::from_utf8_lossy(record.name()).into_owned(),
+            record_type: record.type_().into(),
+            class: record.class(),
+            ttl: record.ttl(),
+            // Not the raw RDATA, since that may hold compression pointers into a packet we no longer have
+            rdata: record.data().to_bytes()?,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<&SavedRecord> for DNSRecord {
+    type Error = Box<dyn Error>;
+
+    fn try_from(saved: &SavedRecord) -> Result<Self, Self::Error> {
+        // Rebuild the record in wire format so the regular parser can take care of the RDATA
+        let mut bytes = encode_dns_name(&saved.name)?;
+        bytes.extend(saved.record_type.to_be_bytes());
+        bytes.extend(saved.class.to_be_bytes());
+        bytes.extend(saved.ttl.to_be_bytes());
+        bytes.extend((saved.rdata.len() as u16).to_be_bytes());
+        bytes.extend(&saved.rdata);
+
+        let (record, _) = DNSRecord::parse((&bytes, 0))?;
+        Ok(record)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Cache {
+    entries: HashMap<(String, RecordType), CachedEntry>,
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records with a TTL of zero are only good for the answer they came in, so they're not stored.
+    pub fn insert(
+        &mut self,
+        domain_name: &str,
+        record_type: RecordType,
+        records: Vec<DNSRecord>,
+        ttl: Duration,
+    ) {
+        if ttl.is_zero() {
+            return;
+        }
+
+        let entry = CachedEntry {
+            records,
+            expires_at: Instant::now() + ttl,
+        };
+        self.entries
+            .insert((domain_name.to_ascii_lowercase(), record_type), entry);
+    }
+
+    /// Returns the entry for the name and type even if it has expired, callers decide if stale data is acceptable.
+    pub fn get(&self, domain_name: &str, record_type: RecordType) -> Option<&CachedEntry> {
+        self.entries
+            .get(&(domain_name.to_ascii_lowercase(), record_type))
+    }
+
+    /// The number of entries, expired ones included.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Writes every entry that hasn't expired yet to `path` as JSON.
+    #[cfg(feature = "serde")]
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let now = Instant::now();
+        let saved: Vec<SavedEntry> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| !entry.is_expired())
+            .map(|((name, record_type), entry)| {
+                Ok(SavedEntry {
+                    name: name.clone(),
+                    record_type: (*record_type).into(),
+                    remaining_ttl: entry.expires_at.duration_since(now).as_secs(),
+                    records: entry
+                        .records
+                        .iter()
+                        .map(SavedRecord::try_from)
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+
+        fs::write(path, serde_json::to_string(&saved)?)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let saved: Vec<SavedEntry> = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        let mut cache = Cache::new();
+        for entry in saved {
+            let records = entry
+                .records
+                .iter()
+                .map(DNSRecord::try_from)
+                .collect::<Result<Vec<_>, _>>()?;
+            cache.insert(
+                &entry.name,
+                entry.record_type.try_into()?,
+                records,
+                Duration::from_secs(entry.remaining_ttl),
+            );
+        }
+
+        Ok(cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_save_and_load_round_trip() {
+        use std::net::Ipv4Addr;
+
+        use crate::{class::Class, record_data::RecordData};
+
+        let path = std::env::temp_dir().join(format!("dns_cache_{}.json", std::process::id()));
+        let mut cache = Cache::new();
+        let record = DNSRecord::new(
+            b"example.com".to_vec(),
+            RecordType::A,
+            Class::In as u16,
+            3600,
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 80)),
+        )
+        .unwrap();
+        cache.insert(
+            "example.com",
+            RecordType::A,
+            vec![record],
+            Duration::from_secs(3600),
+        );
+
+        cache.save(&path).unwrap();
+        let loaded = Cache::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let entry = loaded.get("example.com", RecordType::A).unwrap();
+        assert!(!entry.is_expired());
+        assert_eq!(entry.records()[0].name(), b"example.com");
+        assert_eq!(
+            entry.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 80))
+        );
+    }
+
+    #[test]
+    fn test_remaining_ttl() {
+        let mut cache = Cache::new();
+        cache.insert(
+            "example.com",
+            RecordType::A,
+            vec![],
+            Duration::from_secs(300),
+        );
+        cache.insert(
+            "example.org",
+            RecordType::A,
+            vec![],
+            Duration::from_millis(1),
+        );
+        std::thread::sleep(Duration::from_millis(5));
+
+        let remaining = cache
+            .get("example.com", RecordType::A)
+            .unwrap()
+            .remaining_ttl();
+
+        assert!(remaining <= Duration::from_secs(300) && remaining > Duration::from_secs(299));
+        assert_eq!(
+            cache
+                .get("example.org", RecordType::A)
+                .unwrap()
+                .remaining_ttl(),
+            Duration::ZERO
+        );
+    }
+}
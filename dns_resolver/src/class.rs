@@ -1,10 +1,15 @@
 use std::error::Error;
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u16)]
 pub enum Class {
     #[default]
     In = 1,
+    /// Chaos, these days only used to ask servers about themselves, like `version.bind`
+    Ch = 3,
+    /// Hesiod https://en.wikipedia.org/wiki/Hesiod_(name_service)
+    Hs = 4,
 }
 
 impl TryFrom<u16> for Class {
@@ -13,6 +18,8 @@ impl TryFrom<u16> for Class {
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         let class = match value {
             1 => Class::In,
+            3 => Class::Ch,
+            4 => Class::Hs,
             _ => {
                 return Err(format!("Unknown class type: {}", value).into());
             }
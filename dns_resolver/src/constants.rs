@@ -1,7 +1,35 @@
+use std::net::Ipv4Addr;
+
 pub const UDP_DNS_RESPONSE_SIZE: usize = 1024;
+// Every DNS message may be this large, with or without EDNS
+// https://datatracker.ietf.org/doc/html/rfc6891#section-6.2.5
+pub const MIN_DNS_MESSAGE_SIZE: usize = 512;
 pub const DNS_HEADER_SIZE: usize = 12;
 pub const DNS_QUESTION_SIZE: usize = 4;
 pub const DNS_RECORD_SIZE: usize = 10;
+// More than any sane name needs, few enough to cut a pointer loop short
+pub const MAX_COMPRESSION_POINTERS: usize = 16;
 // https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.1
 pub const RECURSION_DESIRED: u16 = 1 << 8;
+pub const RECURSION_AVAILABLE: u16 = 1 << 7;
 pub const AUTHORITATIVE_NAMESERVER: u16 = 0;
+pub const AUTHORITATIVE_ANSWER: u16 = 1 << 10;
+// https://datatracker.ietf.org/doc/html/rfc4035#section-3.2
+pub const AUTHENTIC_DATA: u16 = 1 << 5;
+pub const CHECKING_DISABLED: u16 = 1 << 4;
+pub const OPCODE_QUERY: u16 = 0;
+// https://datatracker.ietf.org/doc/html/rfc1996
+pub const OPCODE_NOTIFY: u16 = 4;
+// https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.1
+pub const RCODE_NO_ERROR: u16 = 0;
+pub const RCODE_NAME_ERROR: u16 = 3;
+pub const DNS_PORT: u16 = 53;
+// What DNS Flag Day 2020 recommends advertising to avoid IP fragmentation
+pub const DEFAULT_EDNS_UDP_SIZE: u16 = 1232;
+// https://datatracker.ietf.org/doc/html/rfc7873
+pub const EDNS_OPTION_COOKIE: u16 = 10;
+pub const CLIENT_COOKIE_SIZE: usize = 8;
+// https://datatracker.ietf.org/doc/html/rfc7830
+pub const EDNS_OPTION_PADDING: u16 = 12;
+// a.root-servers.net, where every iterative resolution starts
+pub const ROOT_NAME_SERVER: Ipv4Addr = Ipv4Addr::new(198, 41, 0, 4);
@@ -0,0 +1,150 @@
+use std::error::Error;
+
+use crate::{decode_character_string, decode_name, parse_options::NameDecoding};
+
+/// A position in a message that parsers read from front to back. Every read is bounds-checked,
+/// and only moves the cursor forward when it succeeds.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(data: &'a [u8], position: usize) -> Self {
+        Self { data, position }
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    pub(crate) fn at_end(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    /// The next `length` bytes, without moving past them.
+    pub(crate) fn peek_bytes(&self, length: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        self.data
+            .get(self.position..self.position + length)
+            .ok_or_else(|| {
+                format!(
+                    "Reading {} bytes at {} runs past the end of the message",
+                    length, self.position
+                )
+                .into()
+            })
+    }
+
+    pub(crate) fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let bytes = self.peek_bytes(length)?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    /// Reads whatever is left of the `length` bytes that began at `start`, like the rest of a
+    /// record's RDATA.
+    pub(crate) fn read_rest(
+        &mut self,
+        start: usize,
+        length: usize,
+    ) -> Result<&'a [u8], Box<dyn Error>> {
+        let rest = (start + length)
+            .checked_sub(self.position)
+            .ok_or_else(|| format!("Already read past the {} bytes at {}", length, start))?;
+        self.read_bytes(rest)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        Ok(u16::from_be_bytes(self.read_bytes(2)?.try_into()?))
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_be_bytes(self.read_bytes(4)?.try_into()?))
+    }
+
+    /// Reads a possibly compressed name. Compression pointers may point anywhere before the
+    /// name in the whole message, but the cursor only moves past the name itself.
+    pub(crate) fn read_name(&mut self, decoding: NameDecoding) -> Result<String, Box<dyn Error>> {
+        let (name, length) = decode_name(self.data, self.position, decoding)?;
+        self.position += length;
+        Ok(name)
+    }
+
+    pub(crate) fn read_character_string(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (text, length) = decode_character_string(self.data, self.position)?;
+        self.position += length;
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::encode_dns_name;
+
+    use super::*;
+
+    #[test]
+    fn test_reads_advance_the_position() {
+        let data = [0x12, 0x34, 0xde, 0xad, 0xbe, 0xef, 0x07];
+        let mut cursor = Cursor::new(&data, 0);
+
+        assert_eq!(cursor.read_u16().unwrap(), 0x1234);
+        assert_eq!(cursor.read_u32().unwrap(), 0xdeadbeef);
+        assert_eq!(cursor.read_u8().unwrap(), 7);
+        assert_eq!(cursor.position(), data.len());
+    }
+
+    #[test]
+    fn test_read_past_the_end_fails_without_moving() {
+        let data = [0x12, 0x34, 0x56];
+        let mut cursor = Cursor::new(&data, 1);
+
+        assert!(cursor.read_u32().is_err());
+        assert!(cursor.read_bytes(3).is_err());
+        assert_eq!(cursor.peek_bytes(2).unwrap(), [0x34, 0x56]);
+        assert_eq!(cursor.position(), 1);
+        assert_eq!(cursor.read_u16().unwrap(), 0x3456);
+        assert!(cursor.read_u8().is_err());
+        assert_eq!(cursor.position(), 3);
+    }
+
+    #[test]
+    fn test_read_rest() {
+        let data = [1, 2, 3, 4, 5];
+        let mut cursor = Cursor::new(&data, 1);
+        cursor.read_u8().unwrap();
+
+        assert_eq!(cursor.read_rest(1, 3).unwrap(), [3, 4]);
+        assert!(cursor.read_rest(1, 2).is_err());
+        assert!(cursor.read_rest(1, 10).is_err());
+        assert_eq!(cursor.position(), 4);
+    }
+
+    #[test]
+    fn test_read_name_follows_pointers_but_moves_past_the_pointer_only() {
+        let mut data = encode_dns_name("example.com").unwrap();
+        data.extend([3, b'w', b'w', b'w', 0xc0, 0]);
+        let mut cursor = Cursor::new(&data, 13);
+
+        assert_eq!(
+            cursor.read_name(NameDecoding::Strict).unwrap(),
+            "www.example.com"
+        );
+        assert_eq!(cursor.position(), data.len());
+    }
+
+    #[test]
+    fn test_truncated_name_fails_without_moving() {
+        let data = [3, b'w', b'w'];
+        let mut cursor = Cursor::new(&data, 0);
+
+        assert!(cursor.read_name(NameDecoding::Strict).is_err());
+        assert_eq!(cursor.position(), 0);
+    }
+}
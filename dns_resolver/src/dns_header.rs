@@ -1,6 +1,25 @@
 use std::error::Error;
 
+use crate::{constants, error::DnsError};
+
+/// The single-bit flags of a header, https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.1
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct HeaderFlags {
+    /// Set on responses
+    pub qr: bool,
+    pub aa: bool,
+    /// The message was truncated to fit in the UDP payload
+    pub tc: bool,
+    pub rd: bool,
+    pub ra: bool,
+    /// The server validated the answer with DNSSEC
+    pub ad: bool,
+    /// The client asked the server not to validate
+    pub cd: bool,
+}
+
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DNSHeader {
     id: u16,
     flags: u16,
@@ -20,6 +39,33 @@ impl DNSHeader {
         }
     }
 
+    /// Parses just the header at the start of a message and leaves the rest alone, for when the
+    /// ID, flags and counts are all that matter.
+    pub fn peek(message: &[u8]) -> Result<Self, Box<dyn Error>> {
+        DNSHeader::try_from(message)
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+    pub fn flags(&self) -> HeaderFlags {
+        HeaderFlags {
+            qr: self.flags & (1 << 15) != 0,
+            aa: self.flags & constants::AUTHORITATIVE_ANSWER != 0,
+            tc: self.flags & (1 << 9) != 0,
+            rd: self.flags & constants::RECURSION_DESIRED != 0,
+            ra: self.flags & constants::RECURSION_AVAILABLE != 0,
+            ad: self.flags & constants::AUTHENTIC_DATA != 0,
+            cd: self.flags & constants::CHECKING_DISABLED != 0,
+        }
+    }
+    pub fn opcode(&self) -> u16 {
+        (self.flags >> 11) & 0b1111
+    }
+    pub fn rcode(&self) -> u16 {
+        // The response code lives in the lowest 4 bits of the flags
+        self.flags & 0b1111
+    }
     pub fn num_questions(&self) -> u16 {
         self.num_questions
     }
@@ -32,6 +78,15 @@ impl DNSHeader {
     pub fn num_additionals(&self) -> u16 {
         self.num_additionals
     }
+    pub fn set_num_questions(&mut self, num_questions: u16) {
+        self.num_questions = num_questions;
+    }
+    pub fn set_num_answers(&mut self, num_answers: u16) {
+        self.num_answers = num_answers;
+    }
+    pub fn set_num_additionals(&mut self, num_additionals: u16) {
+        self.num_additionals = num_additionals;
+    }
     pub fn to_bytes(&self) -> Vec<u8> {
         [
             self.id.to_be_bytes(),
@@ -50,6 +105,10 @@ impl TryFrom<&[u8]> for DNSHeader {
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         // Each of the 6 fields is a 2-byte integer, so there are 12 bytes in all to read.
+        // Anything after them is left alone.
+        if value.len() < constants::DNS_HEADER_SIZE {
+            return Err(DnsError::ShortResponse(value.len()).into());
+        }
         Ok(DNSHeader {
             id: u16::from_be_bytes(value[0..2].try_into()?),
             flags: u16::from_be_bytes(value[2..4].try_into()?),
@@ -60,3 +119,67 @@ impl TryFrom<&[u8]> for DNSHeader {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_flags_of_non_recursive_response() {
+        let header = DNSHeader::new(0x1234, 0b1000_0101_0000_0000);
+
+        let flags = header.flags();
+
+        assert!(flags.qr && flags.aa && flags.rd);
+        assert!(!flags.ra && !flags.tc);
+    }
+
+    #[test]
+    fn test_authentic_data_flag() {
+        let header = DNSHeader::new(0x1234, 0b1000_0001_1010_0000);
+
+        let flags = header.flags();
+
+        assert!(flags.ad);
+        assert!(!flags.cd);
+    }
+
+    #[test]
+    fn test_peek_ignores_the_body() {
+        let mut message = DNSHeader::new(0xbeef, 0b1000_0001_1000_0000).to_bytes();
+        message[7] = 200;
+        // Nowhere near 200 answers, and garbage at that
+        message.extend([0xff; 4096]);
+
+        let header = DNSHeader::peek(&message).unwrap();
+
+        assert_eq!(header.id(), 0xbeef);
+        assert!(header.flags().qr);
+        assert_eq!(header.opcode(), 0);
+        assert_eq!(header.num_questions(), 1);
+        assert_eq!(header.num_answers(), 200);
+    }
+
+    #[test]
+    fn test_short_slice_is_an_error() {
+        for length in 0..constants::DNS_HEADER_SIZE {
+            let error = DNSHeader::try_from(&[0; 12][..length]).unwrap_err();
+
+            assert!(matches!(
+                error.downcast_ref::<DnsError>(),
+                Some(DnsError::ShortResponse(short)) if *short == length
+            ));
+        }
+    }
+
+    #[test]
+    fn test_peek_short_message() {
+        let error = DNSHeader::peek(&[0; 11]).unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<DnsError>(),
+            Some(DnsError::ShortResponse(11))
+        ));
+    }
+}
@@ -1,9 +1,29 @@
 use crate::{
-    constants, decode_name, dns_header::DNSHeader, dns_question::DNSQuestion, dns_record::DNSRecord,
+    constants, cursor::Cursor, dns_header::DNSHeader, dns_question::DNSQuestion,
+    dns_record::DNSRecord, error::DnsError, parse_options::ParseOptions, record_data::RecordData,
+    record_type::RecordType, rrsig,
 };
-use std::error::Error;
+use std::{error::Error, net::IpAddr};
+
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+
+/// The addresses of every A and AAAA record, in order.
+pub(crate) fn addresses(records: &[DNSRecord]) -> Vec<IpAddr> {
+    records
+        .iter()
+        .filter_map(|record| match record.data() {
+            RecordData::A(ip) => Some(IpAddr::V4(*ip)),
+            RecordData::AAAA(ip) => Some(IpAddr::V6(*ip)),
+            _ => None,
+        })
+        .collect()
+}
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DNSPacket {
     header: DNSHeader,
     questions: Vec<DNSQuestion>,
@@ -29,59 +49,435 @@ impl DNSPacket {
         &self.additionals
     }
 
+    /// The addresses of every A and AAAA record in the answer section, in order.
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        addresses(&self.answers)
+    }
+
+    /// Follows the CNAMEs in the answer section from the question's name, for responses that
+    /// carry the whole chain. Returns every name along the way, starting with the question's,
+    /// and the first address of the last one. `None` if the chain doesn't end in an address.
+    pub fn cname_chain(&self) -> Option<(Vec<String>, IpAddr)> {
+        let question = self.questions.first()?;
+        let mut chain = vec![String::from_utf8_lossy(question.name()).into_owned()];
+
+        // Every CNAME can only be followed once, which also stops at loops
+        for _ in 0..=self.answers.len() {
+            let current = chain.last()?.as_bytes();
+            let owned = |record: &&DNSRecord| record.name().eq_ignore_ascii_case(current);
+
+            if let Some(address) = self
+                .answers
+                .iter()
+                .filter(owned)
+                .find_map(|record| match record.data() {
+                    RecordData::A(ip) => Some(IpAddr::V4(*ip)),
+                    RecordData::AAAA(ip) => Some(IpAddr::V6(*ip)),
+                    _ => None,
+                })
+            {
+                return Some((chain, address));
+            }
+            let target = self
+                .answers
+                .iter()
+                .filter(owned)
+                .find(|record| record.type_() == RecordType::CNAME)?
+                .data()
+                .get_NS()?
+                .to_string();
+            chain.push(target);
+        }
+
+        None
+    }
+
+    /// Whether the answer was synthesized from a wildcard, going by the labels field of the
+    /// RRSIG covering it. Without DNSSEC records in the response there is no telling.
+    pub fn is_wildcard_synthesized(&self, answer: &DNSRecord) -> bool {
+        self.answers.iter().any(|record| match record.data() {
+            RecordData::RRSIG {
+                type_covered,
+                labels,
+                ..
+            } => {
+                *type_covered == answer.type_()
+                    && record.name().eq_ignore_ascii_case(answer.name())
+                    && rrsig::is_wildcard_expansion(answer.name(), *labels)
+            }
+            _ => false,
+        })
+    }
+
+    /// Moves records of the given name and type from the authority section to the answers, for
+    /// servers that put the answer in the wrong place.
+    pub(crate) fn promote_authority_answers(&mut self, domain_name: &str, record_type: RecordType) {
+        let (answers, authorities) =
+            std::mem::take(&mut self.authorities)
+                .into_iter()
+                .partition(|record| {
+                    record.type_() == record_type
+                        && record.name().eq_ignore_ascii_case(domain_name.as_bytes())
+                });
+        self.authorities = authorities;
+        self.answers.extend(answers);
+    }
+
+    /// The header keeps the section counts of the message as it was received.
+    pub(crate) fn drop_authorities_and_additionals(&mut self) {
+        self.authorities = vec![];
+        self.additionals = vec![];
+    }
+
     pub fn parse(data: &[u8]) -> Result<Self, Box<dyn Error>> {
         DNSPacket::try_from(data)
     }
+
+    /// Parses a packet written out in hex, as tools like Wireshark copy it. Whitespace between
+    /// the digits is ignored.
+    pub fn from_hex(hex: &str) -> Result<Self, Box<dyn Error>> {
+        let digits = hex
+            .chars()
+            .filter(|digit| !digit.is_whitespace())
+            .map(|digit| {
+                digit
+                    .to_digit(16)
+                    .ok_or_else(|| format!("{} is not a hex digit", digit))
+            })
+            .collect::<Result<Vec<u32>, _>>()?;
+        if digits.len() % 2 != 0 {
+            return Err("Hex packet has an odd number of digits".into());
+        }
+        let bytes: Vec<u8> = digits
+            .chunks(2)
+            .map(|pair| (pair[0] << 4 | pair[1]) as u8)
+            .collect();
+
+        DNSPacket::try_from(&bytes[..])
+    }
+
+    /// Parses a packet in base64, either standard or the URL-safe unpadded form DoH uses.
+    pub fn from_base64(encoded: &str) -> Result<Self, Box<dyn Error>> {
+        let encoded = encoded.trim();
+        let bytes = STANDARD
+            .decode(encoded)
+            .or_else(|_| URL_SAFE_NO_PAD.decode(encoded))?;
+
+        DNSPacket::try_from(&bytes[..])
+    }
+
+    pub fn parse_with_options(
+        packet: &[u8],
+        options: &ParseOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(DNSPacket::parse_consumed(packet, options)?.0)
+    }
+
+    /// Parses the packet and also returns how many bytes were left over after it, which should be
+    /// zero for a well-formed message. Trailing garbage can point at a bug or an attack.
+    pub fn parse_with_remainder(packet: &[u8]) -> Result<(Self, usize), Box<dyn Error>> {
+        let (parsed, consumed) = DNSPacket::parse_consumed(packet, &ParseOptions::default())?;
+        Ok((parsed, packet.len() - consumed))
+    }
+
+    fn parse_consumed(
+        packet: &[u8],
+        options: &ParseOptions,
+    ) -> Result<(Self, usize), Box<dyn Error>> {
+        if packet.len() < constants::DNS_HEADER_SIZE {
+            return Err(DnsError::ShortResponse(packet.len()).into());
+        }
+        let mut cursor = Cursor::new(packet, 0);
+        let header = DNSHeader::try_from(cursor.read_bytes(constants::DNS_HEADER_SIZE)?)?;
+
+        let mut questions = vec![];
+        for _ in 0..header.num_questions() {
+            let name = cursor.read_name(options.name_decoding)?;
+            let fields = cursor
+                .read_bytes(constants::DNS_QUESTION_SIZE)
+                .map_err(|_| "Question runs past the end of the packet")?;
+            questions.push(DNSQuestion::try_from((name.into_bytes(), fields))?);
+        }
+
+        let mut read_records = |section, count| {
+            let mut records = vec![];
+            for got in 0..count {
+                if cursor.at_end() {
+                    return Err(DnsError::TruncatedSection {
+                        section,
+                        expected: count,
+                        got,
+                    }
+                    .into());
+                }
+                records.push(DNSRecord::read(&mut cursor, options)?);
+            }
+            Ok::<_, Box<dyn Error>>(records)
+        };
+        let answers = read_records("answer", header.num_answers())?;
+        let authorities = read_records("authority", header.num_authorities())?;
+        let additionals = read_records("additional", header.num_additionals())?;
+
+        Ok((
+            DNSPacket {
+                header,
+                questions,
+                answers,
+                authorities,
+                additionals,
+            },
+            cursor.position(),
+        ))
+    }
 }
 
 impl TryFrom<&[u8]> for DNSPacket {
     type Error = Box<dyn Error>;
 
     fn try_from(packet: &[u8]) -> Result<Self, Self::Error> {
-        let header = DNSHeader::try_from(&packet[0..constants::DNS_HEADER_SIZE])?;
-        let mut current_pos = constants::DNS_HEADER_SIZE;
+        DNSPacket::parse_with_options(packet, &ParseOptions::default())
+    }
+}
 
-        let mut questions = vec![];
-        for _ in 0..header.num_questions() {
-            let question = {
-                let (name, current) = decode_name(packet, current_pos)?;
-                current_pos += current;
-                DNSQuestion::try_from((
-                    name.into_bytes().to_vec(),
-                    &packet[current_pos..current_pos + constants::DNS_QUESTION_SIZE],
-                ))?
-            };
-            current_pos += constants::DNS_QUESTION_SIZE;
-            questions.push(question);
+#[cfg(test)]
+mod tests {
+
+    use std::net::Ipv4Addr;
+
+    use proptest::{collection::vec, prelude::*};
+
+    use crate::{encode_dns_name, record_data::RecordData};
+
+    use super::*;
+
+    /// A response to example.com A with a single answer
+    fn example_response() -> Vec<u8> {
+        let mut data = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        data.extend([
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+        ]);
+        data.extend([0, 1, 0, 1]);
+        data.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 192, 0, 2, 80]);
+        data
+    }
+
+    #[test]
+    fn test_parse_with_remainder_of_exact_packet() {
+        let data = example_response();
+
+        let (packet, remainder) = DNSPacket::parse_with_remainder(&data).unwrap();
+
+        assert_eq!(packet.answers().len(), 1);
+        assert_eq!(remainder, 0);
+    }
+
+    #[test]
+    fn test_parse_with_remainder_of_trailing_bytes() {
+        let mut data = example_response();
+        data.extend([0xde, 0xad, 0xbe, 0xef, 0]);
+
+        let (_, remainder) = DNSPacket::parse_with_remainder(&data).unwrap();
+
+        assert_eq!(remainder, 5);
+    }
+
+    #[test]
+    fn test_from_hex() {
+        let data = example_response();
+        let hex: String = data.iter().map(|byte| format!("{:02x} ", byte)).collect();
+
+        let packet = DNSPacket::from_hex(&hex).unwrap();
+
+        assert_eq!(packet.questions()[0].name(), b"example.com");
+        assert_eq!(
+            packet.answers()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 80))
+        );
+    }
+
+    #[test]
+    fn test_addresses() {
+        // example.com ANY, answered with two A records and an AAAA record
+        let packet = DNSPacket::from_hex(
+            "abcd81800001000300000000076578616d706c6503636f6d0000ff0001\
+             c00c000100010000012c00045db8d822\
+             c00c000100010000012c00045db8d823\
+             c00c001c00010000012c001026062800021fcb07682080daaf6b8b2c",
+        )
+        .unwrap();
+
+        assert_eq!(
+            packet.addresses(),
+            [
+                IpAddr::from([93, 184, 216, 34]),
+                IpAddr::from([93, 184, 216, 35]),
+                "2606:2800:21f:cb07:6820:80da:af6b:8b2c".parse().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_over_claimed_answers_are_a_truncated_section() {
+        // Claims 3 answers but only carries the one
+        let mut data = example_response();
+        data[7] = 3;
+
+        let error = DNSPacket::try_from(&data[..]).unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<DnsError>(),
+            Some(DnsError::TruncatedSection {
+                section: "answer",
+                expected: 3,
+                got: 1,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_cname_chain() {
+        // www.example.com CNAME web.example.net CNAME edge.cdn.example A 192.0.2.10
+        let packet = DNSPacket::from_hex(
+            "5ee18180000100030000000003777777076578616d706c6503636f6d00000100\
+             01c00c0005000100000e10001103776562076578616d706c65036e657400c02d\
+             000500010000003c001204656467650363646e076578616d706c6500c04a0001\
+             00010000003c0004c000020a",
+        )
+        .unwrap();
+
+        let (chain, address) = packet.cname_chain().unwrap();
+
+        assert_eq!(
+            chain,
+            ["www.example.com", "web.example.net", "edge.cdn.example"]
+        );
+        assert_eq!(address, IpAddr::from([192, 0, 2, 10]));
+    }
+
+    #[test]
+    fn test_from_base64() {
+        let data = example_response();
+
+        for encoded in [STANDARD.encode(&data), URL_SAFE_NO_PAD.encode(&data)] {
+            let packet = DNSPacket::from_base64(&encoded).unwrap();
+
+            assert_eq!(packet.header().id(), 0x1234);
+            assert_eq!(
+                packet.answers()[0].data().get_A(),
+                Some(&Ipv4Addr::new(192, 0, 2, 80))
+            );
         }
+    }
+
+    #[test]
+    fn test_filtered_record_types_are_skipped() {
+        let mut data = example_response();
+        // Add an authority record for example.com NS a.iana-servers.net
+        data[9] = 1;
+        data.extend([0xc0, 12, 0, 2, 0, 1, 0, 0, 0x0e, 0x10, 0, 20]);
+        data.extend(encode_dns_name("a.iana-servers.net").unwrap());
+        let options = ParseOptions::new().record_types(&[RecordType::A]);
+
+        let packet = DNSPacket::parse_with_options(&data, &options).unwrap();
+
+        assert_eq!(
+            packet.answers()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 80))
+        );
+        let ns = &packet.authorities()[0];
+        assert_eq!(ns.type_(), RecordType::NS);
+        assert!(matches!(ns.data(), RecordData::Skipped));
+        assert!(ns.raw_rdata().is_empty());
+    }
+
+    /// A signed answer to host.wild.example.com A, with `labels` in the RRSIG
+    fn signed_response(labels: u8) -> Vec<u8> {
+        let mut data = vec![0x12, 0x34, 0x81, 0xa0, 0, 1, 0, 2, 0, 0, 0, 0];
+        data.extend(encode_dns_name("host.wild.example.com").unwrap());
+        data.extend([0, 1, 0, 1]);
+        data.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 192, 0, 2, 80]);
+        let mut rdata = vec![0, 1, 13, labels, 0, 0, 0x0e, 0x10];
+        rdata.extend([0x67, 0x1f, 0x5a, 0x00, 0x67, 0x0c, 0xe5, 0x00, 0x9d, 0x6b]);
+        rdata.extend(encode_dns_name("example.com").unwrap());
+        rdata.extend([0xab; 64]);
+        data.extend([0xc0, 12, 0, 46, 0, 1, 0, 0, 0x0e, 0x10]);
+        data.extend((rdata.len() as u16).to_be_bytes());
+        data.extend(rdata);
+        data
+    }
+
+    #[test]
+    fn test_answer_synthesized_from_wildcard() {
+        // Signed as *.wild.example.com, which has 3 labels not counting the *
+        let packet = DNSPacket::parse(&signed_response(3)).unwrap();
 
-        let mut answers = vec![];
-        for _ in 0..header.num_answers() {
-            let (record, cursor) = DNSRecord::parse((packet, current_pos))?;
-            current_pos += cursor;
-            answers.push(record);
+        let RecordData::RRSIG {
+            type_covered,
+            key_tag,
+            signer_name,
+            ..
+        } = packet.answers()[1].data()
+        else {
+            panic!("Expected an RRSIG record");
+        };
+        assert_eq!(*type_covered, RecordType::A);
+        assert_eq!(*key_tag, 0x9d6b);
+        assert_eq!(signer_name, "example.com");
+        assert!(packet.is_wildcard_synthesized(&packet.answers()[0]));
+    }
+
+    #[test]
+    fn test_answer_signed_as_is_is_not_wildcard() {
+        let packet = DNSPacket::parse(&signed_response(4)).unwrap();
+
+        assert!(!packet.is_wildcard_synthesized(&packet.answers()[0]));
+    }
+
+    #[test]
+    fn test_fuzz_seeds_parse_cleanly() {
+        let seeds: [&[u8]; 3] = [
+            include_bytes!("../fuzz/corpus/parse_packet/a_answer.bin"),
+            include_bytes!("../fuzz/corpus/parse_packet/referral_with_glue.bin"),
+            include_bytes!("../fuzz/corpus/parse_packet/cname_with_opt.bin"),
+        ];
+
+        for seed in seeds {
+            let (_, remainder) = DNSPacket::parse_with_remainder(seed).unwrap();
+            assert_eq!(remainder, 0);
         }
+    }
 
-        let mut authorities = vec![];
-        for _ in 0..header.num_authorities() {
-            let (record, cursor) = DNSRecord::parse((packet, current_pos))?;
-            current_pos += cursor;
-            authorities.push(record);
+    proptest! {
+        #[test]
+        fn test_parse_of_random_bytes_never_panics(data in vec(any::<u8>(), 0..512)) {
+            let _ = DNSPacket::try_from(&data[..]);
         }
 
-        let mut additionals = vec![];
-        for _ in 0..header.num_additionals() {
-            let (record, cursor) = DNSRecord::parse((packet, current_pos))?;
-            current_pos += cursor;
-            additionals.push(record);
+        #[test]
+        fn test_parse_of_corrupted_response_never_panics(
+            index in 0..example_response().len(),
+            byte: u8,
+            length in 0..=example_response().len(),
+        ) {
+            // A valid packet with one byte changed gets much further into the parser than noise
+            let mut data = example_response();
+            data[index] = byte;
+            let _ = DNSPacket::try_from(&data[..length]);
         }
+    }
 
-        Ok(DNSPacket {
-            header,
-            questions,
-            answers,
-            authorities,
-            additionals,
-        })
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize_to_json() {
+        let data = example_response();
+        let packet = DNSPacket::try_from(&data[..]).unwrap();
+
+        let json = serde_json::to_value(&packet).unwrap();
+
+        assert_eq!(json["header"]["id"], 0x1234);
+        assert_eq!(json["questions"][0]["name"], "example.com");
+        assert_eq!(json["answers"][0]["name"], "example.com");
+        assert_eq!(json["answers"][0]["ttl"], 3600);
+        assert_eq!(json["answers"][0]["data"]["A"], "192.0.2.80");
     }
 }
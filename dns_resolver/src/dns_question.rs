@@ -3,7 +3,9 @@ use std::error::Error;
 use crate::{class::Class, record_type::RecordType};
 
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DNSQuestion {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_name"))]
     name: Vec<u8>,
     type_: RecordType,
     class: Class,
@@ -14,10 +16,22 @@ impl DNSQuestion {
         Self { name, type_, class }
     }
 
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    pub fn type_(&self) -> RecordType {
+        self.type_
+    }
+
+    pub fn class(&self) -> Class {
+        self.class
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         [
             self.name.clone(),
-            (self.type_ as u16).to_be_bytes().to_vec(),
+            u16::from(self.type_).to_be_bytes().to_vec(),
             (self.class as u16).to_be_bytes().to_vec(),
         ]
         .concat()
@@ -1,17 +1,55 @@
-use std::{error::Error, net::Ipv4Addr};
+use std::{
+    error::Error,
+    net::{Ipv4Addr, Ipv6Addr},
+};
 
-use crate::{constants, decode_name, record_data::RecordData, record_type::RecordType};
+use crate::{
+    cursor::Cursor,
+    encode_dns_name, encode_dns_name_compressed, nsec,
+    parse_options::ParseOptions,
+    record_data::{RecordData, SoaData},
+    record_type::RecordType,
+    rrsig, tsig, NameOffsets,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DNSRecord {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_name"))]
     name: Vec<u8>,
     type_: RecordType,
     class: u16,
     ttl: u32,
     data: RecordData,
+    rdata: Vec<u8>,
+}
+
+/// RDLENGTH is 16 bits, so longer RDATA can't be written.
+fn rdata_length(length: usize) -> Result<u16, Box<dyn Error>> {
+    u16::try_from(length)
+        .map_err(|_| format!("RDATA of {} bytes is longer than 65535", length).into())
 }
 
 impl DNSRecord {
+    /// Fails when the data can't be written in wire format, e.g. a name with a label over 63
+    /// bytes.
+    pub fn new(
+        name: Vec<u8>,
+        type_: RecordType,
+        class: u16,
+        ttl: u32,
+        data: RecordData,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            name,
+            type_,
+            class,
+            ttl,
+            rdata: data.to_bytes()?,
+            data,
+        })
+    }
+
     pub fn name(&self) -> &[u8] {
         &self.name
     }
@@ -27,49 +65,458 @@ impl DNSRecord {
     pub fn data(&self) -> &RecordData {
         &self.data
     }
+    /// The RDATA exactly as it was on the wire, for parsing record types we don't support.
+    pub fn raw_rdata(&self) -> &[u8] {
+        &self.rdata
+    }
+
+    /// The record in wire format, without any name compression.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        // Not the raw RDATA, since that may hold compression pointers into the packet it came from
+        let rdata = self.data.to_bytes()?;
+        let mut bytes = encode_dns_name(&String::from_utf8_lossy(&self.name))?;
+        bytes.extend(u16::from(self.type_).to_be_bytes());
+        bytes.extend(self.class.to_be_bytes());
+        bytes.extend(self.ttl.to_be_bytes());
+        bytes.extend(rdata_length(rdata.len())?.to_be_bytes());
+        bytes.extend(rdata);
+        Ok(bytes)
+    }
+
+    /// Appends the record to `message` with its names compressed against `offsets`, which is
+    /// shared with the rest of the message.
+    pub fn write_compressed(
+        &self,
+        message: &mut Vec<u8>,
+        offsets: &mut NameOffsets,
+    ) -> Result<(), Box<dyn Error>> {
+        encode_dns_name_compressed(&String::from_utf8_lossy(&self.name), message, offsets)?;
+        message.extend(u16::from(self.type_).to_be_bytes());
+        message.extend(self.class.to_be_bytes());
+        message.extend(self.ttl.to_be_bytes());
+        // RDLENGTH is only known once the RDATA is written
+        let length_at = message.len();
+        message.extend([0, 0]);
+        self.data.write_compressed(message, offsets)?;
+        let length = rdata_length(message.len() - length_at - 2)?;
+        message[length_at..length_at + 2].copy_from_slice(&length.to_be_bytes());
+        Ok(())
+    }
 
     pub fn parse((data, cursor): (&[u8], usize)) -> Result<(Self, usize), Box<dyn Error>> {
-        let mut current_pos = cursor;
+        DNSRecord::parse_with_options((data, cursor), &ParseOptions::default())
+    }
 
-        let (name, current) = decode_name(data, current_pos)?;
-        current_pos += current;
+    pub fn parse_with_options(
+        (data, cursor): (&[u8], usize),
+        options: &ParseOptions,
+    ) -> Result<(Self, usize), Box<dyn Error>> {
+        let mut reader = Cursor::new(data, cursor);
+        let record = DNSRecord::read(&mut reader, options)?;
+        Ok((record, reader.position() - cursor))
+    }
 
-        let type_ = u16::from_be_bytes(data[current_pos..current_pos + 2].try_into()?);
-        let class = u16::from_be_bytes(data[current_pos + 2..current_pos + 4].try_into()?);
-        let ttl = u32::from_be_bytes(data[current_pos + 4..current_pos + 8].try_into()?);
-        let data_length = u16::from_be_bytes(data[current_pos + 8..current_pos + 10].try_into()?);
-        current_pos += constants::DNS_RECORD_SIZE;
+    pub(crate) fn read(
+        cursor: &mut Cursor,
+        options: &ParseOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        let name = cursor.read_name(options.name_decoding)?;
+        let type_ = cursor.read_u16()?;
+        let class = cursor.read_u16()?;
+        // A TTL with the top bit set is treated as zero, RFC 2181 section 8
+        let ttl = match cursor.read_u32()? {
+            ttl if ttl > i32::MAX as u32 => 0,
+            ttl => ttl,
+        };
+        let data_length = cursor.read_u16()? as usize;
+        let rdata_start = cursor.position();
+        let rdata = cursor
+            .peek_bytes(data_length)
+            .map_err(|_| "RDATA runs past the end of the packet")?;
 
         let data = match type_.try_into() {
+            Ok(record_type) if !options.keeps(record_type) => {
+                cursor.read_bytes(data_length)?;
+                RecordData::Skipped
+            }
             Ok(RecordType::A) => {
-                let [a, b, c, d] = data[current_pos..current_pos+4] else {
-                    panic!("Expected a valid IPv4 address");
+                let [a, b, c, d] = rdata[..] else {
+                    return Err(format!("A record with {} bytes of RDATA", rdata.len()).into());
                 };
-                current_pos += 4;
+                cursor.read_bytes(4)?;
                 RecordData::A(Ipv4Addr::new(a, b, c, d))
             }
             Ok(RecordType::NS) | Ok(RecordType::CNAME) => {
-                let (name, current) = decode_name(data, current_pos)?;
-                current_pos += current;
-                RecordData::NS(name)
+                RecordData::NS(cursor.read_name(options.name_decoding)?)
             }
-            _ => {
-                let (start, end) = (current_pos, current_pos + data_length as usize);
-                let read_data = data[start..end].to_vec();
-                current_pos += data_length as usize;
-                RecordData::Other(read_data)
+            // Each of the names may be compressed on its own
+            Ok(RecordType::SOA) => RecordData::SOA(SoaData {
+                mname: cursor.read_name(options.name_decoding)?,
+                rname: cursor.read_name(options.name_decoding)?,
+                serial: cursor.read_u32()?,
+                refresh: cursor.read_u32()?,
+                retry: cursor.read_u32()?,
+                expire: cursor.read_u32()?,
+                minimum: cursor.read_u32()?,
+            }),
+            Ok(RecordType::MINFO) => RecordData::MINFO {
+                rmailbx: cursor.read_name(options.name_decoding)?,
+                emailbx: cursor.read_name(options.name_decoding)?,
+            },
+            Ok(RecordType::MX) => RecordData::MX {
+                preference: cursor.read_u16()?,
+                exchange: cursor.read_name(options.name_decoding)?,
+            },
+            Ok(RecordType::AFSDB) => RecordData::AFSDB {
+                subtype: cursor.read_u16()?,
+                hostname: cursor.read_name(options.name_decoding)?,
+            },
+            Ok(RecordType::KX) => RecordData::KX {
+                preference: cursor.read_u16()?,
+                exchanger: cursor.read_name(options.name_decoding)?,
+            },
+            Ok(RecordType::RP) => RecordData::RP {
+                mbox: cursor.read_name(options.name_decoding)?,
+                txt: cursor.read_name(options.name_decoding)?,
+            },
+            Ok(RecordType::HINFO) => RecordData::HINFO {
+                cpu: String::from_utf8_lossy(&cursor.read_character_string()?).into_owned(),
+                os: String::from_utf8_lossy(&cursor.read_character_string()?).into_owned(),
+            },
+            Ok(RecordType::TXT) => {
+                let end = rdata_start + data_length;
+                let mut strings = vec![];
+                while cursor.position() < end {
+                    // The length byte alone could point past the RDATA into the next record
+                    let length = cursor.peek_bytes(1)?[0] as usize;
+                    if cursor.position() + 1 + length > end {
+                        return Err(format!(
+                            "TXT string of {} bytes overruns the {} bytes of RDATA",
+                            length, data_length
+                        )
+                        .into());
+                    }
+                    strings.push(cursor.read_character_string()?);
+                }
+                RecordData::TXT(strings)
+            }
+            Ok(RecordType::AAAA) => {
+                let octets: [u8; 16] = rdata.try_into()?;
+                cursor.read_bytes(16)?;
+                RecordData::AAAA(Ipv6Addr::from(octets))
             }
+            Ok(RecordType::DS) => RecordData::Ds {
+                key_tag: cursor.read_u16()?,
+                algorithm: cursor.read_u8()?,
+                digest_type: cursor.read_u8()?,
+                digest: cursor.read_rest(rdata_start, data_length)?.to_vec(),
+            },
+            Ok(RecordType::RRSIG) => rrsig::parse_rdata(cursor, data_length, options)?,
+            Ok(RecordType::NSEC) => nsec::parse_nsec_rdata(cursor, data_length, options)?,
+            Ok(RecordType::NSEC3) => nsec::parse_nsec3_rdata(cursor, data_length)?,
+            Ok(RecordType::TSIG) => tsig::parse_rdata(cursor, options)?,
+            Ok(RecordType::URI) => RecordData::Uri {
+                priority: cursor.read_u16()?,
+                weight: cursor.read_u16()?,
+                target: String::from_utf8(cursor.read_rest(rdata_start, data_length)?.to_vec())?,
+            },
+            _ => RecordData::Other(cursor.read_bytes(data_length)?.to_vec()),
         };
 
-        Ok((
-            DNSRecord {
-                name: name.into_bytes().to_vec(),
-                type_: type_.try_into()?,
-                class,
-                ttl,
-                data,
+        // Whatever we parsed out of the RDATA has to line up exactly with its declared length
+        let parsed = cursor.position() - rdata_start;
+        if parsed != data_length {
+            return Err(format!(
+                "RDATA length is {} but {} bytes were parsed for a {:?} record",
+                data_length,
+                parsed,
+                RecordType::try_from(type_)?
+            )
+            .into());
+        }
+
+        Ok(DNSRecord {
+            name: name.into_bytes().to_vec(),
+            type_: type_.try_into()?,
+            class,
+            ttl,
+            rdata: match data {
+                RecordData::Skipped => vec![],
+                _ => rdata.to_vec(),
             },
-            current_pos - cursor,
-        ))
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_hinfo() {
+        let mut data = vec![0, 0, 13, 0, 1, 0, 0, 0x0e, 0x10, 0, 13];
+        data.extend([5, b'I', b'N', b'T', b'E', b'L']);
+        data.extend([6, b'L', b'I', b'N', b'U', b'X', b'!']);
+
+        let (record, cursor) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+        assert!(matches!(record.type_(), RecordType::HINFO));
+        assert_eq!(record.data().get_HINFO(), Some(("INTEL", "LINUX!")));
+        assert_eq!(cursor, data.len());
+    }
+
+    #[test]
+    fn test_parse_txt_with_several_strings() {
+        let first = "v=DKIM1; k=rsa; p=".to_string() + &"A".repeat(230);
+        let mut data = vec![0, 0, 16, 0, 1, 0, 0, 0x0e, 0x10];
+        data.extend((first.len() as u16 + 5).to_be_bytes());
+        data.push(first.len() as u8);
+        data.extend(first.as_bytes());
+        data.extend([3, b'Q', b'A', b'B']);
+
+        let (record, cursor) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+        assert_eq!(record.type_(), RecordType::TXT);
+        assert_eq!(record.data().get_TXT().unwrap().len(), 2);
+        assert_eq!(record.data().get_TXT_value(), Some(first + "QAB"));
+        assert_eq!(record.data().to_bytes().unwrap(), record.raw_rdata());
+        assert_eq!(cursor, data.len());
+    }
+
+    #[test]
+    fn test_parse_binary_txt() {
+        let data = [
+            0, 0, 16, 0, 1, 0, 0, 0x0e, 0x10, 0, 6, 2, 0xff, 0xfe, 2, b'o', b'k',
+        ];
+
+        let (record, _) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+        assert_eq!(
+            record.data().get_TXT(),
+            Some(&[vec![0xff, 0xfe], b"ok".to_vec()][..])
+        );
+        assert_eq!(
+            record.data().get_TXT_value(),
+            Some("\u{fffd}\u{fffd}ok".to_string())
+        );
+        assert_eq!(record.data().to_bytes().unwrap(), record.raw_rdata());
+    }
+
+    #[test]
+    fn test_txt_string_overrunning_rdata_is_rejected() {
+        // Claims 200 bytes with only 10 in the RDATA, the rest of the message would cover it
+        let mut data = vec![0, 0, 16, 0, 1, 0, 0, 0x0e, 0x10, 0, 11, 200];
+        data.extend([b'x'; 10]);
+        data.extend([b'y'; 200]);
+
+        let error = DNSRecord::parse((&data[..], 0)).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "TXT string of 200 bytes overruns the 11 bytes of RDATA"
+        );
+    }
+
+    #[test]
+    fn test_parse_soa_with_compressed_names() {
+        // example.com at offset 0 for the names to point back at
+        let mut data = encode_dns_name("example.com").unwrap();
+        let record_start = data.len();
+        data.extend([0xc0, 0, 0, 6, 0, 1, 0, 0, 0x0e, 0x10, 0, 39]);
+        data.extend([3, b'n', b's', b'1', 0xc0, 0]);
+        data.extend([
+            10, b'h', b'o', b's', b't', b'm', b'a', b's', b't', b'e', b'r', 0xc0, 0,
+        ]);
+        for field in [2024010101u32, 7200, 3600, 1209600, 300] {
+            data.extend(field.to_be_bytes());
+        }
+
+        let (record, cursor) = DNSRecord::parse((&data[..], record_start)).unwrap();
+
+        let soa = record.data().get_SOA().unwrap();
+        assert_eq!(soa.mname, "ns1.example.com");
+        assert_eq!(soa.rname, "hostmaster.example.com");
+        assert_eq!(soa.serial, 2024010101);
+        assert_eq!(soa.minimum, 300);
+        assert_eq!(cursor, data.len() - record_start);
+    }
+
+    #[test]
+    fn test_parse_answer_owned_by_pointer_to_question() {
+        // A header, the question for www.example.com and an A record pointing back at it
+        let mut data = vec![0; 12];
+        data.extend(encode_dns_name("www.example.com").unwrap());
+        data.extend([0, 1, 0, 1]);
+        let answer_start = data.len();
+        data.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 192, 0, 2, 1]);
+
+        let (record, consumed) = DNSRecord::parse((&data[..], answer_start)).unwrap();
+
+        assert_eq!(record.name(), b"www.example.com");
+        assert_eq!(record.data().get_A(), Some(&Ipv4Addr::new(192, 0, 2, 1)));
+        // Only the 2 byte pointer counts, not the name it points at
+        assert_eq!(consumed, 2 + 10 + 4);
+    }
+
+    /// A record of `type_` whose RDATA is a 16-bit number followed by a name
+    fn number_and_name_record(type_: u8, number: u16, name: &str) -> Vec<u8> {
+        let name = encode_dns_name(name).unwrap();
+        let mut data = vec![0, 0, type_, 0, 1, 0, 0, 0x0e, 0x10];
+        data.extend((name.len() as u16 + 2).to_be_bytes());
+        data.extend(number.to_be_bytes());
+        data.extend(name);
+        data
+    }
+
+    #[test]
+    fn test_parse_afsdb() {
+        let data = number_and_name_record(18, 1, "afsdb.example.com");
+
+        let (record, cursor) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+        assert_eq!(record.type_(), RecordType::AFSDB);
+        assert_eq!(record.data().get_AFSDB(), Some((1, "afsdb.example.com")));
+        assert_eq!(record.data().to_bytes().unwrap(), record.raw_rdata());
+        assert_eq!(cursor, data.len());
+    }
+
+    #[test]
+    fn test_parse_kx() {
+        let data = number_and_name_record(36, 10, "kx.example.com");
+
+        let (record, cursor) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+        assert_eq!(record.type_(), RecordType::KX);
+        assert_eq!(record.data().get_KX(), Some((10, "kx.example.com")));
+        assert_eq!(record.data().to_bytes().unwrap(), record.raw_rdata());
+        assert_eq!(cursor, data.len());
+    }
+
+    #[test]
+    fn test_parse_ds() {
+        // The DS record of com. in the root zone, a SHA-256 digest of its ECDSA key
+        let digest = [
+            0x8a, 0xcb, 0xb0, 0xcd, 0x28, 0xf4, 0x12, 0x50, 0xa8, 0x0a, 0x49, 0x13, 0x89, 0x42,
+            0x4d, 0x34, 0x15, 0x22, 0xd9, 0x46, 0xb0, 0xda, 0x0c, 0x02, 0x91, 0xf2, 0xd3, 0xd7,
+            0x71, 0xd7, 0x80, 0x5a,
+        ];
+        let mut data = vec![3, b'c', b'o', b'm', 0, 0, 43, 0, 1, 0, 1, 0x51, 0x80, 0, 36];
+        data.extend(19718u16.to_be_bytes());
+        data.extend([13, 2]);
+        data.extend(digest);
+
+        let (record, cursor) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+        assert_eq!(record.type_(), RecordType::DS);
+        assert_eq!(record.data().get_Ds(), Some((19718, 13, 2, &digest[..])));
+        assert_eq!(record.data().to_bytes().unwrap(), record.raw_rdata());
+        assert_eq!(cursor, data.len());
+    }
+
+    #[test]
+    fn test_parse_uri() {
+        let target = "https://www.example.com/path";
+        let mut data = vec![0, 1, 0, 0, 1, 0, 0, 0x0e, 0x10];
+        data.extend((target.len() as u16 + 4).to_be_bytes());
+        data.extend(10u16.to_be_bytes());
+        data.extend(1u16.to_be_bytes());
+        data.extend(target.as_bytes());
+
+        let (record, cursor) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+        assert_eq!(record.type_(), RecordType::URI);
+        assert_eq!(record.data().get_Uri(), Some((10, 1, target)));
+        assert_eq!(record.data().to_bytes().unwrap(), record.raw_rdata());
+        assert_eq!(cursor, data.len());
+    }
+
+    #[test]
+    fn test_rdata_ending_exactly_at_the_end_of_the_buffer() {
+        let ns_name = encode_dns_name("ns.example.com").unwrap();
+        for (type_, rdata) in [
+            (1, vec![192, 0, 2, 1]),
+            (2, ns_name.clone()),
+            (29, vec![0, 0x12, 0x16, 0x13]),
+        ] {
+            let mut data = vec![0, 0, type_, 0, 1, 0, 0, 0x0e, 0x10, 0, rdata.len() as u8];
+            data.extend(&rdata);
+
+            let (record, consumed) = DNSRecord::parse((&data[..], 0)).unwrap();
+            assert_eq!(record.raw_rdata(), &rdata[..]);
+            assert_eq!(consumed, data.len());
+
+            // One byte short of the declared length has to fail rather than panic
+            data.pop();
+            assert!(DNSRecord::parse((&data[..], 0)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_csync_and_zonemd_keep_raw_rdata() {
+        // CSYNC: SOA serial 66, immediate and soaminimum flags, a bitmap of A, NS and AAAA
+        let csync = [0, 0, 0, 66, 0, 3, 0, 4, 0x60, 0, 0, 8];
+        // ZONEMD: serial 2018031500, SIMPLE scheme, SHA384, a digest cut short for the test
+        let zonemd = [0x78, 0x4b, 0xa7, 0x8c, 1, 1, 0xc6, 0x8d, 0x2d, 0xf3];
+        for (type_, record_type, rdata) in [
+            (62, RecordType::CSYNC, &csync[..]),
+            (63, RecordType::ZONEMD, &zonemd[..]),
+        ] {
+            let mut data = vec![0, 0, type_, 0, 1, 0, 0, 0x0e, 0x10, 0, rdata.len() as u8];
+            data.extend(rdata);
+
+            let (record, _) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+            assert_eq!(record.type_(), record_type);
+            assert_eq!(u16::from(record.type_()), type_ as u16);
+            assert_eq!(record.raw_rdata(), rdata);
+            assert_eq!(record.data().get_Other(), Some(rdata));
+        }
+    }
+
+    #[test]
+    fn test_unknown_type_exposes_raw_rdata() {
+        // A LOC record (type 29), which we don't parse
+        let rdata = [0, 0x12, 0x16, 0x13, 0x89, 0x17, 0x2d, 0xd0];
+        let mut data = vec![0, 0, 29, 0, 1, 0, 0, 0x0e, 0x10, 0, rdata.len() as u8];
+        data.extend(rdata);
+
+        let (record, _) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+        assert_eq!(record.type_(), RecordType::Unknown(29));
+        assert_eq!(u16::from(record.type_()), 29);
+        assert_eq!(record.raw_rdata(), &rdata[..]);
+        assert_eq!(record.data().get_Other(), Some(&rdata[..]));
+    }
+
+    #[test]
+    fn test_parse_null() {
+        let rdata = [0x00, 0xff, b'x', 0x7f, 0x80];
+        let mut data = vec![0, 0, 10, 0, 1, 0, 0, 0x0e, 0x10, 0, rdata.len() as u8];
+        data.extend(rdata);
+
+        let (record, cursor) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+        assert_eq!(record.type_(), RecordType::NULL);
+        assert_eq!(record.data().get_Other(), Some(&rdata[..]));
+        assert_eq!(cursor, data.len());
+    }
+
+    #[test]
+    fn test_ttl_with_top_bit_set_is_zero() {
+        let data = [0, 0, 1, 0, 1, 0xff, 0xff, 0xff, 0xff, 0, 4, 192, 0, 2, 80];
+
+        let (record, _) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+        assert_eq!(record.ttl(), 0);
+    }
+
+    #[test]
+    fn test_rdata_length_mismatch_is_rejected() {
+        // An A record claiming 5 bytes of RDATA
+        let data = [0, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 5, 192, 0, 2, 80, 0];
+
+        assert!(DNSRecord::parse((&data[..], 0)).is_err());
     }
 }
@@ -0,0 +1,129 @@
+//! DNS queries over HTTPS, https://datatracker.ietf.org/doc/html/rfc8484
+
+use std::{error::Error, io::Read, time::Duration};
+
+use crate::{dns_packet::DNSPacket, parse_options::ParseOptions};
+
+const MEDIA_TYPE: &str = "application/dns-message";
+
+/// POSTs the query to `url` and parses the DNS message that comes back as the body.
+pub(crate) fn query(
+    agent: &ureq::Agent,
+    url: &str,
+    query: &[u8],
+    timeout: Duration,
+    options: &ParseOptions,
+) -> Result<DNSPacket, Box<dyn Error>> {
+    let response = agent
+        .post(url)
+        .timeout(timeout)
+        .set("Content-Type", MEDIA_TYPE)
+        .set("Accept", MEDIA_TYPE)
+        .send_bytes(query)
+        .map_err(|error| format!("DoH query to {} failed: {}", url, error))?;
+    if response.content_type() != MEDIA_TYPE {
+        return Err(format!(
+            "DoH server {} answered with {} instead of {}",
+            url,
+            response.content_type(),
+            MEDIA_TYPE
+        )
+        .into());
+    }
+
+    // No DNS message is longer than 65535 bytes, whatever the body claims
+    let limit = options.max_response_size.unwrap_or(u16::MAX as usize);
+    let mut body = vec![];
+    response
+        .into_reader()
+        .take(limit as u64 + 1)
+        .read_to_end(&mut body)?;
+    options.check_response_size(body.len())?;
+    if body.len() > u16::MAX as usize {
+        return Err(format!("DoH response of {} bytes is too long", body.len()).into());
+    }
+    DNSPacket::parse_with_options(&body, options)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{Ipv4Addr, TcpListener},
+        thread,
+    };
+
+    use super::*;
+
+    // Answers one request with `content_type` and `body`
+    fn http_server(content_type: &'static str, body: Vec<u8>) -> String {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                line.clear();
+            }
+            let mut request = vec![0; length];
+            reader.read_exact(&mut request).unwrap();
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                content_type,
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn test_wrong_content_type_is_an_error() {
+        let url = http_server("text/html", b"<html></html>".to_vec());
+
+        let error = query(
+            &ureq::Agent::new(),
+            &url,
+            &[0; 12],
+            Duration::from_millis(500),
+            &ParseOptions::default(),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "DoH server {} answered with text/html instead of application/dns-message",
+                url
+            )
+        );
+    }
+
+    #[test]
+    fn test_response_over_the_maximum_size_is_an_error() {
+        let url = http_server(MEDIA_TYPE, vec![0; 600]);
+
+        let error = query(
+            &ureq::Agent::new(),
+            &url,
+            &[0; 12],
+            Duration::from_millis(500),
+            &ParseOptions::default().max_response_size(512),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Response of 513 bytes exceeds the maximum of 512 bytes"
+        );
+    }
+}
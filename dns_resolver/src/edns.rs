@@ -0,0 +1,72 @@
+//! EDNS(0) as described in https://datatracker.ietf.org/doc/html/rfc6891
+
+use std::error::Error;
+
+use crate::{dns_packet::DNSPacket, record_type::RecordType};
+
+/// An option's code and data
+pub type EdnsOption<'a> = (u16, &'a [u8]);
+
+pub(crate) fn opt_record_bytes(udp_size: u16, options: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    // The OPT pseudo-record reuses CLASS for the UDP payload size and TTL for the extended RCODE,
+    // version and flags, all zero for us
+    // https://datatracker.ietf.org/doc/html/rfc6891#section-6.1.2
+    let rdata: Vec<u8> = options
+        .iter()
+        .flat_map(|(code, data)| {
+            [
+                &code.to_be_bytes()[..],
+                &(data.len() as u16).to_be_bytes(),
+                data,
+            ]
+            .concat()
+        })
+        .collect();
+
+    let mut bytes = vec![0];
+    bytes.extend(u16::from(RecordType::OPT).to_be_bytes());
+    bytes.extend(udp_size.to_be_bytes());
+    bytes.extend(0u32.to_be_bytes());
+    bytes.extend((rdata.len() as u16).to_be_bytes());
+    bytes.extend(rdata);
+    bytes
+}
+
+/// Splits the RDATA of an OPT record into its (code, data) options.
+pub fn parse_options(rdata: &[u8]) -> Result<Vec<EdnsOption<'_>>, Box<dyn Error>> {
+    let mut options = vec![];
+    let mut current_pos = 0;
+
+    while current_pos < rdata.len() {
+        let header = rdata
+            .get(current_pos..current_pos + 4)
+            .ok_or("Truncated EDNS option header")?;
+        let code = u16::from_be_bytes([header[0], header[1]]);
+        let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        current_pos += 4;
+
+        let data = rdata
+            .get(current_pos..current_pos + length)
+            .ok_or("EDNS option runs past the end of the OPT record")?;
+        current_pos += length;
+        options.push((code, data));
+    }
+
+    Ok(options)
+}
+
+/// The data of the first option with the given code in the packet's OPT record, if there is one.
+pub fn find_option(packet: &DNSPacket, code: u16) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let Some(opt) = packet
+        .additionals()
+        .iter()
+        .find(|record| record.type_() == RecordType::OPT)
+    else {
+        return Ok(None);
+    };
+
+    Ok(parse_options(opt.raw_rdata())?
+        .into_iter()
+        .find(|(option_code, _)| *option_code == code)
+        .map(|(_, data)| data.to_vec()))
+}
@@ -0,0 +1,61 @@
+use std::{error::Error, fmt, net::IpAddr};
+
+#[derive(Debug, Clone)]
+pub enum DnsError {
+    /// The queried name does not exist (NXDOMAIN)
+    NxDomain(String),
+    /// The queried name exists but has no records of the requested type (NODATA)
+    NoData(String),
+    /// We asked the server to recurse but it can't, and sent no answer either
+    RecursionNotAvailable(String),
+    /// Following CNAMEs took more hops than allowed, `chain` starts with the queried name
+    CnameLoop { chain: Vec<String> },
+    /// The name resolved to an address the resolver's address filter does not allow
+    DeniedAddress { name: String, address: IpAddr },
+    /// A message of this many bytes, too short to even hold a header
+    ShortResponse(usize),
+    /// The message ended after `got` of the `expected` records the header claims for a section
+    TruncatedSection {
+        section: &'static str,
+        expected: u16,
+        got: u16,
+    },
+    /// The caller's cancellation token was set before resolution finished
+    Cancelled,
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsError::NxDomain(name) => write!(f, "{} does not exist", name),
+            DnsError::NoData(name) => write!(f, "{} has no records of the requested type", name),
+            DnsError::RecursionNotAvailable(server) => {
+                write!(f, "{} does not offer recursion", server)
+            }
+            DnsError::CnameLoop { chain } => write!(
+                f,
+                "Gave up following CNAMEs after {} hops: {}",
+                chain.len() - 1,
+                chain.join(" -> ")
+            ),
+            DnsError::DeniedAddress { name, address } => {
+                write!(f, "{} resolved to {}, which is not allowed", name, address)
+            }
+            DnsError::ShortResponse(length) => {
+                write!(f, "Response of {} bytes is shorter than a header", length)
+            }
+            DnsError::TruncatedSection {
+                section,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Message ends after {} of the {} records in its {} section",
+                got, expected, section
+            ),
+            DnsError::Cancelled => write!(f, "Resolution was cancelled"),
+        }
+    }
+}
+
+impl Error for DnsError {}
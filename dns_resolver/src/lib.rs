@@ -1,42 +1,97 @@
 use std::{
+    collections::HashMap,
     error::Error,
-    net::{Ipv4Addr, UdpSocket},
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
 };
 
-use class::Class;
-use dns_header::DNSHeader;
 use dns_packet::DNSPacket;
-use dns_question::DNSQuestion;
 use dns_record::DNSRecord;
+use error::DnsError;
+use parse_options::{NameDecoding, ParseOptions};
 use rand::Rng;
 use record_data::RecordData;
 use record_type::RecordType;
+use resolver::Resolver;
 
+pub mod address_filter;
+pub mod cache;
 pub mod class;
 pub mod constants;
+mod cursor;
 pub mod dns_header;
 pub mod dns_packet;
 pub mod dns_question;
 pub mod dns_record;
+#[cfg(feature = "doh")]
+mod doh;
+pub mod edns;
+pub mod error;
+pub mod nsec;
+pub mod parse_options;
+pub mod query_builder;
+mod rate_limit;
 pub mod record_data;
+pub mod record_stream;
 pub mod record_type;
+pub mod resolver;
+pub mod response_builder;
+mod rrsig;
+mod semaphore;
+#[cfg(feature = "serde")]
+mod serde_name;
+mod single_flight;
+#[cfg(feature = "socks5")]
+mod socks5;
+pub mod transport;
+pub mod tsig;
 
-fn decode_name(data: &[u8], cursor: usize) -> Result<(String, usize), Box<dyn Error>> {
+/// Reads the possibly compressed name starting at `cursor` in the message `data`, returning it
+/// along with the number of bytes it takes up at `cursor`.
+fn decode_name(
+    data: &[u8],
+    cursor: usize,
+    decoding: NameDecoding,
+) -> Result<(String, usize), Box<dyn Error>> {
+    decode_name_following(data, cursor, decoding, 0)
+}
+
+/// `decode_name` for the benches only, it is not part of the API.
+#[doc(hidden)]
+pub fn bench_decode_name(data: &[u8], cursor: usize) -> Result<(String, usize), Box<dyn Error>> {
+    decode_name(data, cursor, NameDecoding::Strict)
+}
+
+fn decode_name_following(
+    data: &[u8],
+    cursor: usize,
+    decoding: NameDecoding,
+    pointers_followed: usize,
+) -> Result<(String, usize), Box<dyn Error>> {
     let mut current_pos: usize = cursor;
     let mut parts = vec![];
-    let mut length = data[current_pos];
+    let mut length = *data
+        .get(current_pos)
+        .ok_or("Name runs past the end of the packet")?;
 
     while length != 0 {
         if length & 0b11000000 != 0 {
-            parts.push(decode_compressed_name(data, current_pos)?.0);
+            parts.push(decode_compressed_name(data, current_pos, decoding, pointers_followed)?.0);
             current_pos += 2;
             return Ok((parts.join("."), current_pos - cursor));
         } else {
             let start = current_pos + 1;
             let end = current_pos + length as usize + 1;
-            parts.push(String::from_utf8(data[start..end].to_vec()).unwrap());
+            let label = data
+                .get(start..end)
+                .ok_or("Label runs past the end of the packet")?;
+            parts.push(decode_label(label, decoding)?);
             current_pos += length as usize + 1;
-            length = data[current_pos];
+            length = *data
+                .get(current_pos)
+                .ok_or("Name runs past the end of the packet")?;
         }
     }
     current_pos += 1;
@@ -44,65 +99,296 @@ fn decode_name(data: &[u8], cursor: usize) -> Result<(String, usize), Box<dyn Er
     Ok((parts.join("."), current_pos - cursor))
 }
 
-fn decode_compressed_name(buf: &[u8], cursor: usize) -> Result<(String, usize), Box<dyn Error>> {
+fn decode_label(label: &[u8], decoding: NameDecoding) -> Result<String, Box<dyn Error>> {
+    let label = match decoding {
+        NameDecoding::Strict => String::from_utf8(label.to_vec())?,
+        NameDecoding::Lossy => String::from_utf8_lossy(label).into_owned(),
+        NameDecoding::Escaped => label
+            .iter()
+            .map(|&byte| match byte {
+                b'.' | b'\\' => format!("\\{}", byte as char),
+                0x21..=0x7e => (byte as char).to_string(),
+                _ => format!("\\{:03}", byte),
+            })
+            .collect(),
+    };
+
+    Ok(label)
+}
+
+fn decode_compressed_name(
+    buf: &[u8],
+    cursor: usize,
+    decoding: NameDecoding,
+    pointers_followed: usize,
+) -> Result<(String, usize), Box<dyn Error>> {
+    // Pointers can be made to loop back on themselves, so don't follow them forever
+    if pointers_followed >= constants::MAX_COMPRESSION_POINTERS {
+        return Err(format!("Name at {} follows too many compression pointers", cursor).into());
+    }
+
     // takes the bottom 6 bits of the length byte, plus the next byte, and converts that to an integer called pointer
     // saves our current position in reader
-    let parts = [buf[cursor] & 0b00111111, buf[cursor + 1]];
+    let bytes = buf
+        .get(cursor..cursor + 2)
+        .ok_or("Compression pointer runs past the end of the packet")?;
+    let parts = [bytes[0] & 0b00111111, bytes[1]];
     let pointer = u16::from_be_bytes(parts) as usize;
+    // Pointers may only refer back to a name earlier in the message
+    if pointer >= cursor {
+        return Err(format!(
+            "Compression pointer at {} points forward to {}",
+            cursor, pointer
+        )
+        .into());
+    }
+
+    decode_name_following(buf, pointer, decoding, pointers_followed + 1)
+}
+
+/// The bytes of a <character-string>, which need not be text.
+fn decode_character_string(data: &[u8], cursor: usize) -> Result<(Vec<u8>, usize), Box<dyn Error>> {
+    // A <character-string> is a single length byte followed by that many bytes
+    // https://datatracker.ietf.org/doc/html/rfc1035#section-3.3
+    let length = *data
+        .get(cursor)
+        .ok_or("Character string runs past the end of the packet")? as usize;
+    let text = data
+        .get(cursor + 1..cursor + 1 + length)
+        .ok_or("Character string runs past the end of the packet")?;
+
+    Ok((text.to_vec(), length + 1))
+}
 
-    decode_name(buf, pointer)
+fn encode_dns_name(domain_name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = vec![];
+    encode_dns_name_into(domain_name, &mut bytes)?;
+    Ok(bytes)
 }
 
-fn encode_dns_name(domain_name: &str) -> Vec<u8> {
-    let mut bytes = domain_name
-        // Split domain name on .
-        .split('.')
-        // Map each label to a length-prefixed byte array
-        .fold(vec![], |mut acc, label| {
-            acc.push(label.len() as u8);
-            acc.extend_from_slice(label.as_bytes());
-            acc
-        });
+/// Appends the wire form of the name to `buf`, saving an allocation per name when building many messages.
+/// Escapes work like in dig: `\.` is a dot within a label and `\ddd` the byte with that decimal value.
+/// Names that don't fit the wire format, like a label over 63 bytes, are an error and leave
+/// `buf` alone.
+pub fn encode_dns_name_into(domain_name: &str, buf: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let labels = split_labels(domain_name);
+    check_label_limits(domain_name, &labels)?;
+    // Map each label to a length-prefixed byte array
+    for label in labels {
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(&label);
+    }
     // Add a 0 byte to terminate the name
-    bytes.push(0);
+    buf.push(0);
+    Ok(())
+}
+
+/// Where each name suffix already written to a message starts, so that later names ending in it
+/// can point back at it, https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.4
+pub type NameOffsets = HashMap<Vec<Vec<u8>>, u16>;
+
+/// Like `encode_dns_name_into`, but ends the name with a pointer to the longest suffix of it
+/// already in `offsets`, and records the suffixes written out in full. `message` has to be the
+/// whole message so far, since pointers are offsets from its start.
+pub fn encode_dns_name_compressed(
+    domain_name: &str,
+    message: &mut Vec<u8>,
+    offsets: &mut NameOffsets,
+) -> Result<(), Box<dyn Error>> {
+    let labels = split_labels(domain_name);
+    check_label_limits(domain_name, &labels)?;
+    for (i, label) in labels.iter().enumerate() {
+        // Names compare case-insensitively
+        let suffix: Vec<_> = labels[i..]
+            .iter()
+            .map(|label| label.to_ascii_lowercase())
+            .collect();
+        if let Some(offset) = offsets.get(&suffix) {
+            message.extend((0xc000 | offset).to_be_bytes());
+            return Ok(());
+        }
+        // A pointer has 14 bits for the offset
+        if message.len() <= 0x3fff {
+            offsets.insert(suffix, message.len() as u16);
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label);
+    }
+    message.push(0);
+    Ok(())
+}
+
+/// Splits the name on the dots that aren't escaped, unescaping the labels. A trailing dot only
+/// says the name is fully qualified, so it adds no label, and the root has none at all.
+fn split_labels(domain_name: &str) -> Vec<Vec<u8>> {
+    if domain_name == "." {
+        return vec![];
+    }
+    let bytes = domain_name.as_bytes();
+    let mut labels = vec![];
+    let mut label = vec![];
+    let mut trailing_dot = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        trailing_dot = bytes[i] == b'.';
+        match bytes[i] {
+            b'\\' => {
+                let decimal = bytes
+                    .get(i + 1..i + 4)
+                    .filter(|digits| digits.iter().all(u8::is_ascii_digit))
+                    .and_then(|digits| std::str::from_utf8(digits).ok()?.parse::<u8>().ok());
+                match (decimal, bytes.get(i + 1)) {
+                    (Some(byte), _) => {
+                        label.push(byte);
+                        i += 4;
+                        continue;
+                    }
+                    (None, Some(&byte)) => {
+                        label.push(byte);
+                        i += 1;
+                    }
+                    // A trailing backslash has nothing to escape
+                    (None, None) => label.push(b'\\'),
+                }
+            }
+            b'.' => labels.push(std::mem::take(&mut label)),
+            byte => label.push(byte),
+        }
+        i += 1;
+    }
+    if !bytes.is_empty() && !trailing_dot {
+        labels.push(label);
+    }
+    labels
+}
+
+/// Checks that the name can be looked up as it is: labels of 1 to 63 letters, digits, hyphens
+/// and underscores, not starting or ending with a hyphen, and at most 255 bytes on the wire.
+/// The root is "" or ".", and any other name may end in a dot but not start with one.
+pub fn validate_name(domain_name: &str) -> Result<(), Box<dyn Error>> {
+    check_name_limits(domain_name)?;
+
+    for label in &split_labels(domain_name) {
+        let text = String::from_utf8_lossy(label);
+        if label.starts_with(b"-") || label.ends_with(b"-") {
+            return Err(format!("Label {} starts or ends with a hyphen", text).into());
+        }
+        if let Some(byte) = label
+            .iter()
+            .find(|byte| !(byte.is_ascii_alphanumeric() || **byte == b'-' || **byte == b'_'))
+        {
+            return Err(format!(
+                "Label {} contains {:?}, which is not allowed",
+                text, *byte as char
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks only what the wire format limits: labels of 1 to 63 bytes and at most 255 bytes in
+/// all. Names that aren't hostnames pass, like wildcards or classless reverse names with a `/`.
+pub(crate) fn check_name_limits(domain_name: &str) -> Result<(), Box<dyn Error>> {
+    check_label_limits(domain_name, &split_labels(domain_name))
+}
+
+fn check_label_limits(domain_name: &str, labels: &[Vec<u8>]) -> Result<(), Box<dyn Error>> {
+    for label in labels {
+        if label.is_empty() {
+            return Err(format!("{} has an empty label", domain_name).into());
+        }
+        if label.len() > 63 {
+            let text = String::from_utf8_lossy(label);
+            return Err(format!("Label {} is longer than 63 bytes", text).into());
+        }
+    }
+    let wire_len = labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1;
+    if wire_len > 255 {
+        return Err(format!("{} takes {} bytes, more than 255", domain_name, wire_len).into());
+    }
 
-    bytes
+    Ok(())
 }
 
-fn build_query(domain_name: &str, record_type: RecordType, flags: u16) -> Vec<u8> {
-    let id = rand::thread_rng().gen_range(0..=std::u16::MAX);
-    let header = DNSHeader::new(id, flags);
+pub fn is_valid_name(domain_name: &str) -> bool {
+    validate_name(domain_name).is_ok()
+}
 
-    let question = DNSQuestion::new(encode_dns_name(domain_name), record_type, Class::In);
+fn randomize_case(domain_name: &str) -> String {
+    // DNS 0x20: servers echo the question back as is, so random casing adds entropy to the query
+    // https://datatracker.ietf.org/doc/html/draft-vixie-dnsext-dns0x20-00
+    let mut rng = rand::thread_rng();
+    domain_name
+        .chars()
+        .map(|c| match rng.gen_bool(0.5) {
+            true => c.to_ascii_uppercase(),
+            false => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
 
-    let mut bytes = header.to_bytes();
-    bytes.extend(question.to_bytes());
+fn time_left(deadline: Instant, domain_name: &str) -> Result<Duration, Box<dyn Error>> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|remaining| !remaining.is_zero())
+        .ok_or_else(|| format!("Deadline exceeded while resolving {}", domain_name).into())
+}
 
-    bytes
+// How often a cancellable query waiting for its response checks whether it was cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Fails with `DnsError::Cancelled` once the token is set.
+fn check_cancelled(cancel: Option<&AtomicBool>) -> Result<(), DnsError> {
+    match cancel {
+        Some(cancel) if cancel.load(Ordering::Relaxed) => Err(DnsError::Cancelled),
+        _ => Ok(()),
+    }
 }
 
 fn send_query(
-    ip: Ipv4Addr,
+    socket: &UdpSocket,
+    server: SocketAddr,
     domain_name: &str,
-    record_type: RecordType,
+    query: &[u8],
+    deadline: Instant,
+    cancel: Option<&AtomicBool>,
+    options: &ParseOptions,
 ) -> Result<DNSPacket, Box<dyn Error>> {
-    let query = build_query(
-        domain_name,
-        record_type,
-        constants::AUTHORITATIVE_NAMESERVER,
-    );
+    time_left(deadline, domain_name)?;
 
-    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).expect("Couldn't bind to address");
-    socket
-        .send_to(&query, (ip, 53))
-        .expect("Something went wrong...");
+    socket.send_to(query, server)?;
 
     let mut response_buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
-    socket
-        .recv_from(&mut response_buffer)
-        .expect("Expecected a response");
+    loop {
+        // Whatever is left until the deadline is how long this query may wait for a response,
+        // in slices short enough to notice a cancellation in time
+        let timeout = time_left(deadline, domain_name)?;
+        socket.set_read_timeout(Some(match cancel {
+            Some(_) => timeout.min(CANCEL_POLL_INTERVAL),
+            None => timeout,
+        }))?;
+        let (length, from) = match socket.recv_from(&mut response_buffer) {
+            Ok(received) => received,
+            Err(error)
+                if cancel.is_some()
+                    && matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                    && Instant::now() < deadline =>
+            {
+                check_cancelled(cancel)?;
+                continue;
+            }
+            Err(_) => return Err(format!("No response from {} before the deadline", server).into()),
+        };
 
-    DNSPacket::try_from(&response_buffer[..])
+        // The socket is reused across queries, so late responses to earlier queries are skipped
+        let response = &response_buffer[..length];
+        if from == server && response.get(0..2) == query.get(0..2) {
+            options.check_response_size(length)?;
+            return DNSPacket::parse_with_options(response, options);
+        }
+    }
 }
 
 fn get_answer(packet: &DNSPacket) -> Option<&DNSRecord> {
@@ -110,19 +396,47 @@ fn get_answer(packet: &DNSPacket) -> Option<&DNSRecord> {
     packet
         .answers()
         .iter()
-        .find(|record| matches!(&record.type_(), RecordType::A | RecordType::CNAME))
+        .find(|record| matches!(&record.type_(), RecordType::A))
 }
 
-fn get_name_server_ip(packet: &DNSPacket) -> Option<&Ipv4Addr> {
-    //return the first A record in the Additional section
+fn get_cname(packet: &DNSPacket) -> Option<&str> {
+    //return the target of the first CNAME record in the Answer section
     packet
-        .additionals()
+        .answers()
         .iter()
-        .find(|record| matches!(&record.data(), RecordData::A(_)))
-        .map(|record| match record.data() {
-            RecordData::A(ref ip) => ip,
-            _ => panic!("Expected A record"),
-        })
+        .find(|record| matches!(&record.type_(), RecordType::CNAME))
+        .and_then(|record| record.data().get_NS())
+}
+
+fn get_glue<'a>(packet: &'a DNSPacket, server_zone: &str) -> Option<&'a DNSRecord> {
+    //return the first glue A record in the Additional section for one of the referred nameservers.
+    //Glue only counts for nameservers within the delegated zone, anything else could be a server
+    //trying to plant addresses for names it has no authority over. The delegated zone in turn has
+    //to be below `server_zone`, the zone of the server that sent the referral.
+    let zone = get_referral_zone(packet).filter(|zone| in_bailiwick(zone, server_zone))?;
+    let name_servers: Vec<&str> = packet
+        .authorities()
+        .iter()
+        .filter(|record| record.type_() == RecordType::NS)
+        .filter_map(|record| record.data().get_NS())
+        .filter(|name| in_bailiwick(name, zone))
+        .collect();
+
+    packet.additionals().iter().find_map(|record| {
+        let name = std::str::from_utf8(record.name())
+            .ok()
+            .filter(|name| in_bailiwick(name, zone))?;
+        match record.data() {
+            RecordData::A(_)
+                if name_servers
+                    .iter()
+                    .any(|name_server| name_server.eq_ignore_ascii_case(name)) =>
+            {
+                Some(record)
+            }
+            _ => None,
+        }
+    })
 }
 
 fn get_name_server(packet: &DNSPacket) -> Option<&str> {
@@ -130,61 +444,415 @@ fn get_name_server(packet: &DNSPacket) -> Option<&str> {
     packet
         .authorities()
         .iter()
-        .find(|record| matches!(&record.data(), RecordData::NS(_)))
+        .find(|record| record.type_() == RecordType::NS)
         .map(|record| match record.data() {
             RecordData::NS(ref name) => name.as_str(),
             _ => panic!("Expected NS record"),
         })
 }
 
-fn resolve(domain_name: &str, record_type: RecordType) -> Result<Ipv4Addr, Box<dyn Error>> {
-    let mut name_server_ip = Ipv4Addr::new(198, 41, 0, 4);
+fn get_referral_zone(packet: &DNSPacket) -> Option<&str> {
+    //return the owner name of the first NS record in the Authority section, the zone we are referred to
+    packet
+        .authorities()
+        .iter()
+        .find(|record| record.type_() == RecordType::NS)
+        .and_then(|record| std::str::from_utf8(record.name()).ok())
+}
 
-    loop {
-        println!("Resolving {} from {}", domain_name, name_server_ip);
-        let packet = send_query(name_server_ip, domain_name, record_type)?;
-
-        if let Some(answer) = get_answer(&packet) {
-            match (answer.data(), answer.type_()) {
-                (RecordData::A(_), RecordType::A) => return Ok(*answer.data().get_A().unwrap()),
-                (RecordData::NS(ref name), RecordType::CNAME) => {
-                    return resolve(name, RecordType::A);
-                }
-                _ => panic!("Expected type A or CNAME record!"),
-            }
-        }
+/// Whether the name is the zone or one of the names below it. Every name is below the root, "".
+fn in_bailiwick(domain_name: &str, zone: &str) -> bool {
+    let domain_name = domain_name.trim_end_matches('.').to_ascii_lowercase();
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
 
-        if let Some(ip) = get_name_server_ip(&packet) {
-            name_server_ip = *ip;
-        } else {
-            let Some(ns_domain) = get_name_server(&packet) else {
-                panic!("Expected packet");
-            };
-            name_server_ip = resolve(ns_domain, RecordType::A)?;
-        }
+    zone.is_empty() || domain_name == zone || domain_name.ends_with(&format!(".{}", zone))
+}
+
+pub fn resolve(
+    domain_name: &str,
+    record_type: RecordType,
+    deadline: Instant,
+) -> Result<Ipv4Addr, Box<dyn Error>> {
+    let packet = lookup(domain_name, record_type, deadline)?;
+
+    match get_answer(&packet).map(|answer| answer.data()) {
+        Some(RecordData::A(ip)) => Ok(*ip),
+        _ => Err(format!("No A record found for {}", domain_name).into()),
     }
 }
 
+/// Like `resolve`, but `select` picks the answer to return instead of it being the first A record.
+pub fn resolve_with<F>(
+    domain_name: &str,
+    record_type: RecordType,
+    deadline: Instant,
+    select: F,
+) -> Result<DNSRecord, Box<dyn Error>>
+where
+    F: Fn(&[DNSRecord]) -> Option<&DNSRecord>,
+{
+    Resolver::new().resolve_with(domain_name, record_type, deadline, select)
+}
+
+pub fn lookup(
+    domain_name: &str,
+    record_type: RecordType,
+    deadline: Instant,
+) -> Result<DNSPacket, Box<dyn Error>> {
+    Resolver::new().resolve_iteratively(domain_name, record_type, deadline)
+}
+
 #[cfg(test)]
 mod tests {
 
+    use std::net::Ipv6Addr;
+
+    use crate::{class::Class, dns_question::DNSQuestion, query_builder::QueryBuilder};
+
     use super::*;
 
     #[test]
     fn test_encode() {
         let name = "google.com";
         let expected: Vec<u8> = vec![6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109, 0];
-        let result = encode_dns_name(name);
+        let result = encode_dns_name(name).unwrap();
 
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_encode_into_matches_encode() {
+        let mut buf = vec![0xff];
+        encode_dns_name_into("mail.example.com", &mut buf).unwrap();
+
+        assert_eq!(buf[0], 0xff);
+        assert_eq!(buf[1..], encode_dns_name("mail.example.com").unwrap());
+    }
+
+    #[test]
+    fn test_encode_rejects_names_that_dont_fit_the_wire_format() {
+        let long_label = format!("{}.com", "a".repeat(64));
+        let long_name = [&"a".repeat(63)[..]; 4].join(".");
+        let mut buf = vec![0xff];
+
+        for name in [&long_label[..], &long_name, "a..com"] {
+            assert!(encode_dns_name_into(name, &mut buf).is_err(), "{}", name);
+            assert!(
+                encode_dns_name_compressed(name, &mut buf, &mut NameOffsets::new()).is_err(),
+                "{}",
+                name
+            );
+        }
+
+        assert_eq!(buf, [0xff]);
+        assert_eq!(
+            QueryBuilder::new(&long_label, RecordType::A)
+                .build()
+                .unwrap_err()
+                .to_string(),
+            format!("Label {} is longer than 63 bytes", "a".repeat(64))
+        );
+    }
+
+    #[test]
+    fn test_encode_escaped_labels() {
+        let mut expected = vec![3, b'a', b'.', b'b'];
+        expected.extend(encode_dns_name("example.com").unwrap());
+
+        assert_eq!(encode_dns_name("a\\.b.example.com").unwrap(), expected);
+        assert_eq!(
+            encode_dns_name("\\065\\\\b.com").unwrap(),
+            [3, b'A', b'\\', b'b', 3, b'c', b'o', b'm', 0]
+        );
+    }
+
+    #[test]
+    fn test_encode_fully_qualified_and_root() {
+        assert_eq!(
+            encode_dns_name("example.com.").unwrap(),
+            encode_dns_name("example.com").unwrap()
+        );
+        assert_eq!(encode_dns_name("").unwrap(), [0]);
+        assert_eq!(encode_dns_name(".").unwrap(), [0]);
+        // An escaped dot at the end is part of the last label
+        assert_eq!(encode_dns_name("a\\.").unwrap(), [2, b'a', b'.', 0]);
+    }
+
+    #[test]
+    fn test_escaped_names_round_trip() {
+        let name = "a\\.b\\000.example.com";
+        let data = encode_dns_name(name).unwrap();
+
+        let (decoded, _) = decode_name(&data, 0, NameDecoding::Escaped).unwrap();
+
+        assert_eq!(decoded, name);
+    }
+
+    #[test]
+    fn test_valid_names() {
+        let longest_label = "a".repeat(63);
+        let longest_name = [&"a".repeat(61)[..]; 4].join(".");
+        for name in [
+            "",
+            ".",
+            "example.com",
+            "example.com.",
+            "www-1.example.com",
+            "_dmarc.example.com",
+            "xn--bcher-kva.example",
+            &longest_label,
+            &longest_name,
+        ] {
+            assert!(is_valid_name(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let long_label = "a".repeat(64);
+        let long_name = [&"a".repeat(63)[..]; 4].join(".");
+        for name in [
+            ".example.com",
+            "example..com",
+            "example.com..",
+            "-example.com",
+            "example-.com",
+            "exa mple.com",
+            "example.com/",
+            "bücher.example",
+            &long_label,
+            &long_name,
+        ] {
+            assert!(!is_valid_name(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_compression_pointer_loop_is_rejected() {
+        // The question name is a pointer to itself
+        let mut data = header_bytes([1, 0, 0, 0]);
+        data.extend([0xc0, 12, 0, 1, 0, 1]);
+
+        assert!(DNSPacket::try_from(&data[..]).is_err());
+    }
+
+    #[test]
+    fn test_forward_compression_pointer_is_rejected() {
+        // The question name points at the answer's owner name, which comes after it
+        let mut data = header_bytes([1, 1, 0, 0]);
+        data.extend([0xc0, 18, 0, 1, 0, 1]);
+        data.extend(encode_dns_name("example.com").unwrap());
+        data.extend([0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 192, 0, 2, 80]);
+
+        let error = DNSPacket::try_from(&data[..]).unwrap_err();
+
+        assert!(error.to_string().contains("points forward"));
+    }
+
+    #[test]
+    fn test_out_of_bailiwick_glue_is_ignored() {
+        let mut data = header_bytes([1, 0, 1, 2]);
+        data.extend(
+            DNSQuestion::new(
+                encode_dns_name("www.example.com").unwrap(),
+                RecordType::A,
+                Class::In,
+            )
+            .to_bytes(),
+        );
+        data.extend(record_bytes(
+            "example.com",
+            RecordType::NS,
+            &encode_dns_name("ns1.example.com").unwrap(),
+        ));
+        // Neither of these is glue for ns1.example.com
+        data.extend(record_bytes(
+            "www.bank.com",
+            RecordType::A,
+            &[192, 0, 2, 66],
+        ));
+        data.extend(record_bytes(
+            "ns1.example.org",
+            RecordType::A,
+            &[192, 0, 2, 67],
+        ));
+
+        let packet = DNSPacket::try_from(&data[..]).unwrap();
+
+        assert_eq!(get_name_server(&packet), Some("ns1.example.com"));
+        assert_eq!(
+            get_glue(&packet, "").and_then(|glue| glue.data().get_A()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_glue_outside_the_referring_servers_zone_is_ignored() {
+        let mut data = header_bytes([1, 0, 1, 1]);
+        data.extend(
+            DNSQuestion::new(
+                encode_dns_name("www.example.org").unwrap(),
+                RecordType::A,
+                Class::In,
+            )
+            .to_bytes(),
+        );
+        data.extend(record_bytes(
+            "example.org",
+            RecordType::NS,
+            &encode_dns_name("ns1.example.org").unwrap(),
+        ));
+        data.extend(record_bytes(
+            "ns1.example.org",
+            RecordType::A,
+            &[192, 0, 2, 67],
+        ));
+
+        let packet = DNSPacket::try_from(&data[..]).unwrap();
+
+        // Fine coming from the root or the org servers, not from a server for example.com
+        assert_eq!(
+            get_glue(&packet, "").and_then(|glue| glue.data().get_A()),
+            Some(&Ipv4Addr::new(192, 0, 2, 67))
+        );
+        assert!(get_glue(&packet, "org")
+            .and_then(|glue| glue.data().get_A())
+            .is_some());
+        assert_eq!(
+            get_glue(&packet, "example.com").and_then(|glue| glue.data().get_A()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_in_bailiwick() {
+        assert!(in_bailiwick("www.example.com", ""));
+        assert!(in_bailiwick("www.Example.com.", "example.com"));
+        assert!(in_bailiwick("example.com", "example.com"));
+        assert!(!in_bailiwick("www.badexample.com", "example.com"));
+        assert!(!in_bailiwick("example.org", "example.com"));
+    }
+
     #[test]
     fn test_resolve() {
-        let result = resolve("www.twitter.com", RecordType::A);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let result = resolve("www.twitter.com", RecordType::A, deadline);
         println!("Result: {:?}", result);
     }
 
+    fn header_bytes(counts: [u16; 4]) -> Vec<u8> {
+        let [questions, answers, authorities, additionals] = counts;
+        [
+            0x1234u16,
+            0x8000,
+            questions,
+            answers,
+            authorities,
+            additionals,
+        ]
+        .iter()
+        .flat_map(|field| field.to_be_bytes())
+        .collect()
+    }
+
+    fn record_bytes(name: &str, type_: RecordType, rdata: &[u8]) -> Vec<u8> {
+        let mut bytes = encode_dns_name(name).unwrap();
+        bytes.extend(u16::from(type_).to_be_bytes());
+        bytes.extend((Class::In as u16).to_be_bytes());
+        bytes.extend(3600u32.to_be_bytes());
+        bytes.extend((rdata.len() as u16).to_be_bytes());
+        bytes.extend(rdata);
+        bytes
+    }
+
+    #[test]
+    fn test_cname_with_referral_for_target() {
+        let mut data = header_bytes([1, 1, 1, 1]);
+        data.extend(
+            DNSQuestion::new(
+                encode_dns_name("www.example.com").unwrap(),
+                RecordType::A,
+                Class::In,
+            )
+            .to_bytes(),
+        );
+        data.extend(record_bytes(
+            "www.example.com",
+            RecordType::CNAME,
+            &encode_dns_name("www.example.net").unwrap(),
+        ));
+        data.extend(record_bytes(
+            "example.net",
+            RecordType::NS,
+            &encode_dns_name("ns1.example.net").unwrap(),
+        ));
+        data.extend(record_bytes(
+            "ns1.example.net",
+            RecordType::A,
+            &[192, 0, 2, 53],
+        ));
+
+        let packet = DNSPacket::try_from(&data[..]).unwrap();
+
+        assert!(get_answer(&packet).is_none());
+        assert_eq!(get_cname(&packet), Some("www.example.net"));
+        assert_eq!(get_name_server(&packet), Some("ns1.example.net"));
+        assert_eq!(
+            get_glue(&packet, "").and_then(|glue| glue.data().get_A()),
+            Some(&Ipv4Addr::new(192, 0, 2, 53))
+        );
+    }
+
+    #[test]
+    fn test_referral_with_compressed_glue_names() {
+        let mut data = header_bytes([1, 0, 2, 2]);
+        // Question for www.example.com, "example.com" starts at offset 16
+        data.extend(
+            DNSQuestion::new(
+                encode_dns_name("www.example.com").unwrap(),
+                RecordType::A,
+                Class::In,
+            )
+            .to_bytes(),
+        );
+        let ttl_and_class = [0, 1, 0, 0, 0x0e, 0x10];
+        // Authority: example.com NS ns1.example.com, rdata starts at offset 45
+        data.extend([0xc0, 16, 0, 2]);
+        data.extend(ttl_and_class);
+        data.extend([0, 6, 3, b'n', b's', b'1', 0xc0, 16]);
+        // Authority: example.com NS ns2.example.com, rdata starts at offset 63
+        data.extend([0xc0, 16, 0, 2]);
+        data.extend(ttl_and_class);
+        data.extend([0, 6, 3, b'n', b's', b'2', 0xc0, 16]);
+        // Additional: glue for ns1 and ns2, with names pointing into the authority rdata
+        data.extend([0xc0, 45, 0, 1]);
+        data.extend(ttl_and_class);
+        data.extend([0, 4, 192, 0, 2, 1]);
+        data.extend([0xc0, 63, 0, 1]);
+        data.extend(ttl_and_class);
+        data.extend([0, 4, 192, 0, 2, 2]);
+
+        let packet = DNSPacket::try_from(&data[..]).unwrap();
+
+        let glue: Vec<_> = packet
+            .additionals()
+            .iter()
+            .map(|record| (record.name(), record.data().get_A().copied()))
+            .collect();
+        assert_eq!(
+            glue,
+            vec![
+                (&b"ns1.example.com"[..], Some(Ipv4Addr::new(192, 0, 2, 1))),
+                (&b"ns2.example.com"[..], Some(Ipv4Addr::new(192, 0, 2, 2))),
+            ]
+        );
+        assert_eq!(get_name_server(&packet), Some("ns1.example.com"));
+        assert_eq!(
+            get_glue(&packet, "").and_then(|glue| glue.data().get_A()),
+            Some(&Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+
     #[test]
     fn test_decode_name() {
         let mut data = [0; constants::UDP_DNS_RESPONSE_SIZE];
@@ -198,7 +866,51 @@ mod tests {
             }
         }
 
-        let decoded_name = decode_name(&data[..], 0).unwrap();
+        let decoded_name = decode_name(&data[..], 0, NameDecoding::Strict).unwrap();
         assert_eq!(decoded_name.0, "www.google.com");
     }
+
+    const NON_ASCII_NAME: [u8; 14] = [
+        4, b'c', b'a', b'f', 0xe9, 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0,
+    ];
+
+    #[test]
+    fn test_decode_name_strict() {
+        assert!(decode_name(&NON_ASCII_NAME, 0, NameDecoding::Strict).is_err());
+    }
+
+    #[test]
+    fn test_decode_name_lossy() {
+        let (name, _) = decode_name(&NON_ASCII_NAME, 0, NameDecoding::Lossy).unwrap();
+        assert_eq!(name, "caf\u{fffd}.example");
+    }
+
+    #[test]
+    fn test_decode_name_escaped() {
+        let (name, _) = decode_name(&NON_ASCII_NAME, 0, NameDecoding::Escaped).unwrap();
+        assert_eq!(name, "caf\\233.example");
+    }
+
+    #[test]
+    fn test_send_failure_is_an_error() {
+        // An IPv4 socket can't send to an IPv6 server
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let server = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 53);
+        let query = QueryBuilder::new("example.com", RecordType::A)
+            .build()
+            .unwrap();
+
+        let error = send_query(
+            &socket,
+            server,
+            "example.com",
+            &query,
+            Instant::now() + Duration::from_millis(500),
+            None,
+            &ParseOptions::default(),
+        )
+        .unwrap_err();
+
+        assert!(error.downcast_ref::<std::io::Error>().is_some());
+    }
 }
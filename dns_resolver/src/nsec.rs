@@ -0,0 +1,157 @@
+//! Authenticated denial of existence, NSEC from https://datatracker.ietf.org/doc/html/rfc4034#section-4
+//! and NSEC3 from https://datatracker.ietf.org/doc/html/rfc5155#section-3
+
+use std::error::Error;
+
+use crate::{
+    cursor::Cursor, parse_options::ParseOptions, record_data::RecordData, record_type::RecordType,
+};
+
+/// Parses NSEC RDATA of `length` bytes.
+pub(crate) fn parse_nsec_rdata(
+    cursor: &mut Cursor,
+    length: usize,
+    options: &ParseOptions,
+) -> Result<RecordData, Box<dyn Error>> {
+    let start = cursor.position();
+    let next_domain_name = cursor.read_name(options.name_decoding)?;
+    let bitmap = cursor.read_rest(start, length)?;
+
+    Ok(RecordData::NSEC {
+        next_domain_name,
+        types: decode_types(bitmap)?,
+    })
+}
+
+/// Parses NSEC3 RDATA of `length` bytes.
+pub(crate) fn parse_nsec3_rdata(
+    cursor: &mut Cursor,
+    length: usize,
+) -> Result<RecordData, Box<dyn Error>> {
+    let start = cursor.position();
+    let hash_algorithm = cursor.read_u8()?;
+    let flags = cursor.read_u8()?;
+    let iterations = cursor.read_u16()?;
+    let salt_length = cursor.read_u8()? as usize;
+    let salt = cursor.read_bytes(salt_length)?.to_vec();
+    let hash_length = cursor.read_u8()? as usize;
+    let next_hashed_owner = cursor.read_bytes(hash_length)?.to_vec();
+    let bitmap = cursor.read_rest(start, length)?;
+
+    Ok(RecordData::NSEC3 {
+        hash_algorithm,
+        flags,
+        iterations,
+        salt,
+        next_hashed_owner,
+        types: decode_types(bitmap)?,
+    })
+}
+
+/// Decodes a type bitmap into type numbers. The bitmap is a series of windows of 256 types each:
+/// the window number, the length of its bitmap and the bitmap itself, where the most significant
+/// bit of the first byte stands for the first type of the window. A truncated window at the end
+/// is ignored.
+pub fn decode_type_bitmap(bitmap: &[u8]) -> Vec<u16> {
+    let mut types = vec![];
+    let mut current_pos = 0;
+
+    while let [window, length, ..] = bitmap[current_pos..] {
+        let Some(bits) = bitmap.get(current_pos + 2..current_pos + 2 + length as usize) else {
+            break;
+        };
+        current_pos += 2 + length as usize;
+
+        for (index, byte) in bits.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0b1000_0000 >> bit) != 0 {
+                    types.push((window as u16) << 8 | (index * 8 + bit) as u16);
+                }
+            }
+        }
+    }
+
+    types
+}
+
+fn decode_types(bitmap: &[u8]) -> Result<Vec<RecordType>, Box<dyn Error>> {
+    decode_type_bitmap(bitmap)
+        .into_iter()
+        .map(RecordType::try_from)
+        .collect()
+}
+
+/// Encodes `types` as a type bitmap, the inverse of [`decode_types`].
+pub(crate) fn encode_types(types: &[RecordType]) -> Vec<u8> {
+    let mut type_numbers: Vec<u16> = types.iter().map(|&type_| type_.into()).collect();
+    type_numbers.sort_unstable();
+    type_numbers.dedup();
+
+    let mut bytes = vec![];
+    let mut window_start = 0;
+    for type_number in type_numbers {
+        let (window, offset) = ((type_number >> 8) as u8, (type_number & 0xff) as usize);
+        if bytes.is_empty() || bytes[window_start] != window {
+            window_start = bytes.len();
+            bytes.extend([window, 0]);
+        }
+        // Grow the window's bitmap up to the byte holding this type
+        while (bytes[window_start + 1] as usize) <= offset / 8 {
+            bytes.push(0);
+            bytes[window_start + 1] += 1;
+        }
+        bytes[window_start + 2 + offset / 8] |= 0b1000_0000 >> (offset % 8);
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{dns_record::DNSRecord, encode_dns_name};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_nsec() {
+        // example.com NSEC host.example.com A MX RRSIG NSEC TYPE1234, from RFC 4034 section 4.3
+        let mut rdata = encode_dns_name("host.example.com").unwrap();
+        rdata.extend([0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03]);
+        rdata.extend([0x04, 0x1b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        rdata.extend([0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        rdata.extend([0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20]);
+        let mut data = encode_dns_name("example.com").unwrap();
+        data.extend([0, 47, 0, 1, 0, 0, 0x0e, 0x10]);
+        data.extend((rdata.len() as u16).to_be_bytes());
+        data.extend(&rdata);
+
+        let (record, _) = DNSRecord::parse((&data[..], 0)).unwrap();
+
+        let RecordData::NSEC {
+            next_domain_name,
+            types,
+        } = record.data()
+        else {
+            panic!("Expected an NSEC record");
+        };
+        assert_eq!(next_domain_name, "host.example.com");
+        assert_eq!(
+            types,
+            &[
+                RecordType::A,
+                RecordType::MX,
+                RecordType::RRSIG,
+                RecordType::NSEC,
+                RecordType::Unknown(1234),
+            ]
+        );
+        assert_eq!(record.data().to_bytes().unwrap(), rdata);
+    }
+
+    #[test]
+    fn test_decode_type_bitmap() {
+        // Window 0, one byte: A (1), NS (2) and SOA (6)
+        assert_eq!(decode_type_bitmap(&[0x00, 0x01, 0b0110_0010]), [1, 2, 6]);
+    }
+}
@@ -0,0 +1,67 @@
+use std::error::Error;
+
+use crate::{constants, record_type::RecordType};
+
+/// What to do with label bytes that don't make up valid UTF-8, like `caf\xe9`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum NameDecoding {
+    /// Fail to parse the name
+    #[default]
+    Strict,
+    /// Replace invalid sequences with U+FFFD
+    Lossy,
+    /// Write every byte outside of printable ASCII as a dig-style `\ddd` escape
+    Escaped,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    pub(crate) name_decoding: NameDecoding,
+    // None keeps records of every type
+    pub(crate) record_types: Option<Vec<RecordType>>,
+    // None accepts messages of any size the transport can carry
+    pub(crate) max_response_size: Option<usize>,
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn name_decoding(mut self, name_decoding: NameDecoding) -> Self {
+        self.name_decoding = name_decoding;
+        self
+    }
+
+    /// Only parse the RDATA of records with these types. Any other record is kept as
+    /// `RecordData::Skipped`, without a copy of its RDATA, which saves memory on large responses.
+    pub fn record_types(mut self, record_types: &[RecordType]) -> Self {
+        self.record_types = Some(record_types.to_vec());
+        self
+    }
+
+    /// Reject responses larger than this many bytes. Over TCP the length prefix is checked before
+    /// anything is allocated for the message. Sizes below 512 bytes count as 512, which any
+    /// message may take up.
+    pub fn max_response_size(mut self, max_response_size: usize) -> Self {
+        self.max_response_size = Some(max_response_size.max(constants::MIN_DNS_MESSAGE_SIZE));
+        self
+    }
+
+    pub(crate) fn check_response_size(&self, size: usize) -> Result<(), Box<dyn Error>> {
+        match self.max_response_size {
+            Some(max) if size > max => Err(format!(
+                "Response of {} bytes exceeds the maximum of {} bytes",
+                size, max
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn keeps(&self, record_type: RecordType) -> bool {
+        self.record_types
+            .as_ref()
+            .is_none_or(|record_types| record_types.contains(&record_type))
+    }
+}
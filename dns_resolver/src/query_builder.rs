@@ -0,0 +1,348 @@
+use std::{error::Error, ops::BitOr};
+
+use rand::Rng;
+
+use crate::{
+    class::Class, constants, dns_header::DNSHeader, edns::opt_record_bytes, encode_dns_name_into,
+    record_type::RecordType, split_labels,
+};
+
+/// Header flags to set on a query, combined with `|`, e.g. `QueryFlags::RD | QueryFlags::CD`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct QueryFlags(u16);
+
+impl QueryFlags {
+    pub const NONE: Self = Self(0);
+    pub const AA: Self = Self(constants::AUTHORITATIVE_ANSWER);
+    pub const RD: Self = Self(constants::RECURSION_DESIRED);
+    pub const AD: Self = Self(constants::AUTHENTIC_DATA);
+    pub const CD: Self = Self(constants::CHECKING_DISABLED);
+
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for QueryFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl From<QueryFlags> for u16 {
+    fn from(value: QueryFlags) -> Self {
+        value.0
+    }
+}
+
+/// Builds the wire bytes of a query for a single question.
+#[derive(Debug, Clone)]
+pub struct QueryBuilder {
+    id: Option<u16>,
+    domain_name: String,
+    record_type: RecordType,
+    class: Class,
+    opcode: u16,
+    flags: QueryFlags,
+    edns_udp_size: Option<u16>,
+    edns_options: Vec<(u16, Vec<u8>)>,
+    edns_padding: Option<usize>,
+}
+
+impl QueryBuilder {
+    pub fn new(domain_name: &str, record_type: RecordType) -> Self {
+        Self {
+            id: None,
+            domain_name: domain_name.to_string(),
+            record_type,
+            class: Class::In,
+            opcode: constants::OPCODE_QUERY,
+            flags: QueryFlags::NONE,
+            edns_udp_size: Some(constants::DEFAULT_EDNS_UDP_SIZE),
+            edns_options: vec![],
+            edns_padding: None,
+        }
+    }
+
+    /// A NOTIFY message telling a secondary that the zone changed, see RFC 1996.
+    pub fn notify(zone: &str) -> Self {
+        Self::new(zone, RecordType::SOA)
+            .opcode(constants::OPCODE_NOTIFY)
+            .flags(QueryFlags::AA)
+            .without_edns()
+    }
+
+    /// Use this ID instead of a random one, e.g. to keep a client's ID when forwarding its query.
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn class(mut self, class: Class) -> Self {
+        self.class = class;
+        self
+    }
+
+    pub fn opcode(mut self, opcode: u16) -> Self {
+        self.opcode = opcode;
+        self
+    }
+
+    pub fn flags(mut self, flags: QueryFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// The UDP payload size advertised in the OPT record, 512, 1232 and 4096 are common choices.
+    pub fn edns_udp_size(mut self, edns_udp_size: u16) -> Self {
+        self.edns_udp_size = Some(edns_udp_size);
+        self
+    }
+
+    /// Adds an option to the OPT record, like a cookie (code 10).
+    pub fn edns_option(mut self, code: u16, data: Vec<u8>) -> Self {
+        self.edns_options.push((code, data));
+        self
+    }
+
+    /// Pad the message with the padding option (code 12) to a multiple of `block_size` bytes, so
+    /// that the size of an encrypted query gives away less about the name. RFC 8467 recommends
+    /// 128. Has no effect without EDNS.
+    pub fn edns_padding(mut self, block_size: usize) -> Self {
+        self.edns_padding = Some(block_size).filter(|&block_size| block_size > 0);
+        self
+    }
+
+    /// Leave out the OPT record, for servers that predate EDNS.
+    pub fn without_edns(mut self) -> Self {
+        self.edns_udp_size = None;
+        self
+    }
+
+    /// How many bytes `build` will return, without building the message.
+    pub fn wire_len(&self) -> usize {
+        self.unpadded_len() + self.padding_len().map_or(0, |padding| 4 + padding)
+    }
+
+    /// The number of zeroes in the padding option, if there is one.
+    fn padding_len(&self) -> Option<usize> {
+        let block_size = self.edns_padding.filter(|_| self.edns_udp_size.is_some())?;
+        // The option's own code and length count towards the padded size too
+        let len = self.unpadded_len() + 4;
+        Some((block_size - len % block_size) % block_size)
+    }
+
+    fn unpadded_len(&self) -> usize {
+        // Every label gets a length byte, plus the terminating 0
+        let name_len = split_labels(&self.domain_name)
+            .iter()
+            .map(|label| label.len() + 1)
+            .sum::<usize>()
+            + 1;
+        let question_len = name_len + constants::DNS_QUESTION_SIZE;
+        // Root name, type, class, TTL and RDATA length, followed by the options
+        let opt_len = match self.edns_udp_size {
+            Some(_) => {
+                self.edns_options
+                    .iter()
+                    .map(|(_, data)| 4 + data.len())
+                    .sum::<usize>()
+                    + 1
+                    + constants::DNS_RECORD_SIZE
+            }
+            None => 0,
+        };
+
+        constants::DNS_HEADER_SIZE + question_len + opt_len
+    }
+
+    /// Fails when the name doesn't fit the wire format, e.g. a label over 63 bytes.
+    pub fn build(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let id = self
+            .id
+            .unwrap_or_else(|| rand::thread_rng().gen_range(0..=u16::MAX));
+        // The opcode sits in the 4 bits right after QR
+        let flags = self.flags.bits() | (self.opcode & 0b1111) << 11;
+        let mut header = DNSHeader::new(id, flags);
+        if self.edns_udp_size.is_some() {
+            header.set_num_additionals(1);
+        }
+
+        // The question is written straight into the message rather than through a DNSQuestion,
+        // which would need the name in a buffer of its own
+        let mut bytes = header.to_bytes();
+        encode_dns_name_into(&self.domain_name, &mut bytes)?;
+        bytes.extend(u16::from(self.record_type).to_be_bytes());
+        bytes.extend((self.class as u16).to_be_bytes());
+        if let Some(udp_size) = self.edns_udp_size {
+            let mut options = self.edns_options.clone();
+            if let Some(padding) = self.padding_len() {
+                options.push((constants::EDNS_OPTION_PADDING, vec![0; padding]));
+            }
+            bytes.extend(opt_record_bytes(udp_size, &options));
+        }
+
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{dns_packet::DNSPacket, edns};
+
+    use super::*;
+
+    #[test]
+    fn test_opt_record_defaults_to_1232() {
+        let query = QueryBuilder::new("example.com", RecordType::A)
+            .build()
+            .unwrap();
+
+        let packet = DNSPacket::try_from(&query[..]).unwrap();
+
+        assert_eq!(packet.additionals()[0].type_(), RecordType::OPT);
+        assert_eq!(packet.additionals()[0].class(), 1232);
+    }
+
+    #[test]
+    fn test_opt_record_carries_configured_udp_size() {
+        let query = QueryBuilder::new("example.com", RecordType::A)
+            .edns_udp_size(4096)
+            .build()
+            .unwrap();
+
+        let packet = DNSPacket::try_from(&query[..]).unwrap();
+
+        assert_eq!(packet.header().num_additionals(), 1);
+        assert_eq!(packet.additionals()[0].type_(), RecordType::OPT);
+        assert_eq!(packet.additionals()[0].class(), 4096);
+    }
+
+    #[test]
+    fn test_wire_len_matches_built_query() {
+        let builders = [
+            QueryBuilder::new("example.com", RecordType::A),
+            QueryBuilder::new("a.very.long.subdomain.example.org", RecordType::AAAA),
+            QueryBuilder::new("example.com.", RecordType::MX).without_edns(),
+            QueryBuilder::new("a\\.b\\065.example.com", RecordType::A),
+            QueryBuilder::new("example.com", RecordType::A)
+                .edns_option(constants::EDNS_OPTION_COOKIE, vec![0; 8]),
+            QueryBuilder::notify("example.com"),
+            QueryBuilder::new("example.com", RecordType::A).edns_padding(128),
+            QueryBuilder::new("example.com", RecordType::A)
+                .edns_padding(128)
+                .without_edns(),
+        ];
+
+        for builder in builders {
+            assert_eq!(
+                builder.wire_len(),
+                builder.build().unwrap().len(),
+                "{:?}",
+                builder
+            );
+        }
+        // A trailing dot doesn't change the query
+        assert_eq!(
+            QueryBuilder::new("example.com.", RecordType::MX)
+                .id(1)
+                .build()
+                .unwrap(),
+            QueryBuilder::new("example.com", RecordType::MX)
+                .id(1)
+                .build()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_flags_compose() {
+        let flags = QueryFlags::RD | QueryFlags::CD;
+
+        assert_eq!(u16::from(flags), 0b0000_0001_0001_0000);
+        assert!(flags.contains(QueryFlags::RD));
+        assert!(!flags.contains(QueryFlags::AD));
+    }
+
+    #[test]
+    fn test_checking_disabled_query() {
+        let query = QueryBuilder::new("example.com", RecordType::A)
+            .flags(QueryFlags::RD | QueryFlags::CD)
+            .build()
+            .unwrap();
+
+        assert_eq!(query[3] & 0b0001_0000, 0b0001_0000);
+        let packet = DNSPacket::try_from(&query[..]).unwrap();
+        assert!(packet.header().flags().cd);
+    }
+
+    #[test]
+    fn test_chaos_class_query() {
+        let query = QueryBuilder::new("version.bind", RecordType::TXT)
+            .class(Class::Ch)
+            .build()
+            .unwrap();
+
+        let packet = DNSPacket::try_from(&query[..]).unwrap();
+
+        assert_eq!(packet.questions()[0].type_(), RecordType::TXT);
+        assert_eq!(packet.questions()[0].class(), Class::Ch);
+    }
+
+    #[test]
+    fn test_padding_fills_the_block() {
+        let query = QueryBuilder::new("example.com", RecordType::A)
+            .edns_padding(128)
+            .build()
+            .unwrap();
+
+        assert_eq!(query.len(), 128);
+        let packet = DNSPacket::try_from(&query[..]).unwrap();
+        let padding = edns::find_option(&packet, constants::EDNS_OPTION_PADDING)
+            .unwrap()
+            .unwrap();
+        assert!(padding.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_explicit_id() {
+        let query = QueryBuilder::new("example.com", RecordType::A)
+            .id(0x1234)
+            .build()
+            .unwrap();
+
+        assert_eq!(query[..2], [0x12, 0x34]);
+    }
+
+    #[test]
+    fn test_notify_message() {
+        let query = QueryBuilder::notify("example.com").build().unwrap();
+
+        let packet = DNSPacket::try_from(&query[..]).unwrap();
+
+        assert_eq!(packet.header().opcode(), constants::OPCODE_NOTIFY);
+        assert_eq!(query[2] >> 3 & 0b1111, 4);
+        assert_eq!(packet.questions()[0].name(), b"example.com");
+        assert_eq!(packet.questions()[0].type_(), RecordType::SOA);
+    }
+
+    #[test]
+    fn test_notify_response_rcode() {
+        let mut response = QueryBuilder::notify("example.com").build().unwrap();
+        // Flip QR and answer with NOTIMP, like a server that doesn't support NOTIFY
+        response[2] |= 0b1000_0000;
+        response[3] = 4;
+
+        let packet = DNSPacket::try_from(&response[..]).unwrap();
+
+        assert_eq!(packet.header().opcode(), constants::OPCODE_NOTIFY);
+        assert_eq!(packet.header().rcode(), 4);
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+/// A token bucket allowing `rate` queries a second, in bursts of up to `rate` queries.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: u32,
+    // Goes negative while queries are queued up waiting for tokens
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token and returns how long to wait before it may be used.
+    pub(crate) fn take(&mut self) -> Duration {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * self.rate as f64;
+        self.tokens = (self.tokens + refill).min(self.rate as f64) - 1.0;
+        self.last_refill = now;
+
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / self.rate as f64),
+            false => Duration::ZERO,
+        }
+    }
+
+    /// Returns a token taken for a query that ended up not being sent.
+    pub(crate) fn give_back(&mut self) {
+        self.tokens = (self.tokens + 1.0).min(self.rate as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_waits_once_the_burst_is_used_up() {
+        let mut bucket = TokenBucket::new(10);
+
+        for _ in 0..10 {
+            assert_eq!(bucket.take(), Duration::ZERO);
+        }
+        let wait = bucket.take();
+
+        assert!(wait > Duration::from_millis(90) && wait <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_given_back_token_is_taken_again() {
+        let mut bucket = TokenBucket::new(10);
+        for _ in 0..10 {
+            bucket.take();
+        }
+
+        let first = bucket.take();
+        bucket.give_back();
+        let second = bucket.take();
+
+        assert!(second <= first);
+        assert!(second > Duration::from_millis(90));
+    }
+}
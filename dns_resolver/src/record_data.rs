@@ -1,25 +1,384 @@
-use std::net::Ipv4Addr;
+use std::{
+    error::Error,
+    net::{Ipv4Addr, Ipv6Addr},
+};
 
-#[derive(Debug)]
+use crate::{
+    encode_dns_name, encode_dns_name_compressed, nsec, record_type::RecordType, NameOffsets,
+};
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RecordData {
     A(Ipv4Addr),
     NS(String),
+    SOA(SoaData),
+    HINFO {
+        cpu: String,
+        os: String,
+    },
+    MINFO {
+        rmailbx: String,
+        emailbx: String,
+    },
+    MX {
+        preference: u16,
+        exchange: String,
+    },
+    /// The character-strings as they were split up on the wire, at most 255 bytes each. They are
+    /// binary data and need not be UTF-8.
+    TXT(Vec<Vec<u8>>),
+    RP {
+        mbox: String,
+        txt: String,
+    },
+    AFSDB {
+        subtype: u16,
+        hostname: String,
+    },
+    AAAA(Ipv6Addr),
+    KX {
+        preference: u16,
+        exchanger: String,
+    },
+    /// The digest of a child zone's DNSKEY, published in the parent zone
+    Ds {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+    },
+    RRSIG {
+        type_covered: RecordType,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        /// Seconds since the epoch, modulo 2^32
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer_name: String,
+        signature: Vec<u8>,
+    },
+    NSEC {
+        next_domain_name: String,
+        types: Vec<RecordType>,
+    },
+    NSEC3 {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next_hashed_owner: Vec<u8>,
+        types: Vec<RecordType>,
+    },
+    Tsig {
+        algorithm: String,
+        /// Seconds since the epoch, only 48 bits on the wire
+        time_signed: u64,
+        fudge: u16,
+        mac: Vec<u8>,
+        original_id: u16,
+        error: u16,
+        other: Vec<u8>,
+    },
+    Uri {
+        priority: u16,
+        weight: u16,
+        /// The URI itself, the rest of the RDATA as is rather than a name
+        target: String,
+    },
     Other(Vec<u8>),
+    /// A record left out by `ParseOptions::record_types`
+    Skipped,
+}
+
+/// The start of authority of a zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SoaData {
+    /// The primary name server of the zone
+    pub mname: String,
+    /// The mailbox of whoever is responsible for the zone, with the @ as the first dot
+    pub rname: String,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    /// How long negative answers from the zone may be cached, RFC 2308
+    pub minimum: u32,
+}
+
+/// The bytes after a one-byte length, which can't say more than 255. Longer values are an error
+/// rather than being cut short on the wire.
+fn length_prefixed(field: &str, bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let length = u8::try_from(bytes.len())
+        .map_err(|_| format!("{} of {} bytes is longer than 255", field, bytes.len()))?;
+    Ok([&[length][..], bytes].concat())
 }
 
+#[allow(non_snake_case)]
 impl RecordData {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(match self {
+            RecordData::A(ip) => ip.octets().to_vec(),
+            RecordData::NS(name) => encode_dns_name(name)?,
+            RecordData::SOA(soa) => [encode_dns_name(&soa.mname)?, encode_dns_name(&soa.rname)?]
+                .into_iter()
+                .chain(
+                    [soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum]
+                        .map(|field| field.to_be_bytes().to_vec()),
+                )
+                .collect::<Vec<_>>()
+                .concat(),
+            RecordData::HINFO { cpu, os } => [
+                length_prefixed("HINFO CPU", cpu.as_bytes())?,
+                length_prefixed("HINFO OS", os.as_bytes())?,
+            ]
+            .concat(),
+            RecordData::TXT(strings) => strings
+                .iter()
+                .map(|string| length_prefixed("TXT string", string))
+                .collect::<Result<Vec<_>, _>>()?
+                .concat(),
+            RecordData::MINFO { rmailbx, emailbx } => {
+                [encode_dns_name(rmailbx)?, encode_dns_name(emailbx)?].concat()
+            }
+            RecordData::MX {
+                preference: number,
+                exchange: name,
+            }
+            | RecordData::AFSDB {
+                subtype: number,
+                hostname: name,
+            }
+            | RecordData::KX {
+                preference: number,
+                exchanger: name,
+            } => [number.to_be_bytes().to_vec(), encode_dns_name(name)?].concat(),
+            RecordData::RP { mbox, txt } => {
+                [encode_dns_name(mbox)?, encode_dns_name(txt)?].concat()
+            }
+            RecordData::AAAA(ip) => ip.octets().to_vec(),
+            RecordData::Ds {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => [
+                key_tag.to_be_bytes().to_vec(),
+                vec![*algorithm, *digest_type],
+                digest.clone(),
+            ]
+            .concat(),
+            RecordData::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => [
+                u16::from(*type_covered).to_be_bytes().to_vec(),
+                vec![*algorithm, *labels],
+                original_ttl.to_be_bytes().to_vec(),
+                expiration.to_be_bytes().to_vec(),
+                inception.to_be_bytes().to_vec(),
+                key_tag.to_be_bytes().to_vec(),
+                encode_dns_name(signer_name)?,
+                signature.clone(),
+            ]
+            .concat(),
+            RecordData::NSEC {
+                next_domain_name,
+                types,
+            } => [
+                encode_dns_name(next_domain_name)?,
+                nsec::encode_types(types),
+            ]
+            .concat(),
+            RecordData::NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner,
+                types,
+            } => [
+                vec![*hash_algorithm, *flags],
+                iterations.to_be_bytes().to_vec(),
+                length_prefixed("NSEC3 salt", salt)?,
+                length_prefixed("NSEC3 next hashed owner", next_hashed_owner)?,
+                nsec::encode_types(types),
+            ]
+            .concat(),
+            RecordData::Tsig {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other,
+            } => [
+                encode_dns_name(algorithm)?,
+                time_signed.to_be_bytes()[2..].to_vec(),
+                fudge.to_be_bytes().to_vec(),
+                (mac.len() as u16).to_be_bytes().to_vec(),
+                mac.clone(),
+                original_id.to_be_bytes().to_vec(),
+                error.to_be_bytes().to_vec(),
+                (other.len() as u16).to_be_bytes().to_vec(),
+                other.clone(),
+            ]
+            .concat(),
+            RecordData::Uri {
+                priority,
+                weight,
+                target,
+            } => [
+                priority.to_be_bytes().to_vec(),
+                weight.to_be_bytes().to_vec(),
+                target.as_bytes().to_vec(),
+            ]
+            .concat(),
+            RecordData::Other(data) => data.clone(),
+            RecordData::Skipped => vec![],
+        })
+    }
+
+    /// Appends the RDATA to `message` like `to_bytes`, compressing the names of the RFC 1035
+    /// types. Names in any other type must stay uncompressed, RFC 3597 section 4.
+    pub fn write_compressed(
+        &self,
+        message: &mut Vec<u8>,
+        offsets: &mut NameOffsets,
+    ) -> Result<(), Box<dyn Error>> {
+        match self {
+            RecordData::NS(name) => encode_dns_name_compressed(name, message, offsets)?,
+            RecordData::SOA(soa) => {
+                encode_dns_name_compressed(&soa.mname, message, offsets)?;
+                encode_dns_name_compressed(&soa.rname, message, offsets)?;
+                for field in [soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum] {
+                    message.extend(field.to_be_bytes());
+                }
+            }
+            RecordData::MINFO { rmailbx, emailbx } => {
+                encode_dns_name_compressed(rmailbx, message, offsets)?;
+                encode_dns_name_compressed(emailbx, message, offsets)?;
+            }
+            RecordData::MX {
+                preference,
+                exchange,
+            } => {
+                message.extend(preference.to_be_bytes());
+                encode_dns_name_compressed(exchange, message, offsets)?;
+            }
+            _ => message.extend(self.to_bytes()?),
+        }
+        Ok(())
+    }
+
     pub fn get_A(&self) -> Option<&Ipv4Addr> {
         match self {
             RecordData::A(ip) => Some(ip),
             _ => None,
         }
     }
+    /// The address as it appears on the wire, without going through `Ipv4Addr`. There is no
+    /// single `as_bytes` for A and AAAA, since that would have to allocate to return either size.
+    pub fn get_A_bytes(&self) -> Option<[u8; 4]> {
+        self.get_A().map(Ipv4Addr::octets)
+    }
     pub fn get_NS(&self) -> Option<&str> {
         match self {
             RecordData::NS(name) => Some(name),
             _ => None,
         }
     }
+    pub fn get_SOA(&self) -> Option<&SoaData> {
+        match self {
+            RecordData::SOA(soa) => Some(soa),
+            _ => None,
+        }
+    }
+    pub fn get_HINFO(&self) -> Option<(&str, &str)> {
+        match self {
+            RecordData::HINFO { cpu, os } => Some((cpu, os)),
+            _ => None,
+        }
+    }
+    pub fn get_TXT(&self) -> Option<&[Vec<u8>]> {
+        match self {
+            RecordData::TXT(strings) => Some(strings),
+            _ => None,
+        }
+    }
+    /// The TXT strings joined back together, which is how values longer than 255 bytes like
+    /// DKIM keys are meant to be read. Bytes that aren't UTF-8 come out as U+FFFD.
+    pub fn get_TXT_value(&self) -> Option<String> {
+        self.get_TXT()
+            .map(|strings| String::from_utf8_lossy(&strings.concat()).into_owned())
+    }
+    pub fn get_MX(&self) -> Option<(u16, &str)> {
+        match self {
+            RecordData::MX {
+                preference,
+                exchange,
+            } => Some((*preference, exchange)),
+            _ => None,
+        }
+    }
+    pub fn get_AFSDB(&self) -> Option<(u16, &str)> {
+        match self {
+            RecordData::AFSDB { subtype, hostname } => Some((*subtype, hostname)),
+            _ => None,
+        }
+    }
+    pub fn get_KX(&self) -> Option<(u16, &str)> {
+        match self {
+            RecordData::KX {
+                preference,
+                exchanger,
+            } => Some((*preference, exchanger)),
+            _ => None,
+        }
+    }
+    /// The key tag, algorithm, digest type and digest
+    pub fn get_Ds(&self) -> Option<(u16, u8, u8, &[u8])> {
+        match self {
+            RecordData::Ds {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => Some((*key_tag, *algorithm, *digest_type, digest)),
+            _ => None,
+        }
+    }
+    pub fn get_AAAA(&self) -> Option<&Ipv6Addr> {
+        match self {
+            RecordData::AAAA(ip) => Some(ip),
+            _ => None,
+        }
+    }
+    /// The address as it appears on the wire, without going through `Ipv6Addr`
+    pub fn get_AAAA_bytes(&self) -> Option<[u8; 16]> {
+        self.get_AAAA().map(Ipv6Addr::octets)
+    }
+    pub fn get_Uri(&self) -> Option<(u16, u16, &str)> {
+        match self {
+            RecordData::Uri {
+                priority,
+                weight,
+                target,
+            } => Some((*priority, *weight, target)),
+            _ => None,
+        }
+    }
     pub fn get_Other(&self) -> Option<&[u8]> {
         match self {
             RecordData::Other(data) => Some(data),
@@ -27,3 +386,42 @@ impl RecordData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_address_bytes_match_octets() {
+        let v4 = Ipv4Addr::new(192, 0, 2, 80);
+        let v6 = "2001:db8::80".parse::<Ipv6Addr>().unwrap();
+
+        assert_eq!(RecordData::A(v4).get_A_bytes(), Some(v4.octets()));
+        assert_eq!(RecordData::AAAA(v6).get_AAAA_bytes(), Some(v6.octets()));
+        assert_eq!(RecordData::A(v4).get_AAAA_bytes(), None);
+    }
+
+    #[test]
+    fn test_values_over_255_bytes_are_rejected() {
+        let long = "a".repeat(256);
+
+        let txt = RecordData::TXT(vec![b"v=spf1".to_vec(), long.clone().into_bytes()]);
+        let hinfo = RecordData::HINFO {
+            cpu: long,
+            os: "Linux".to_string(),
+        };
+
+        assert_eq!(
+            txt.to_bytes().unwrap_err().to_string(),
+            "TXT string of 256 bytes is longer than 255"
+        );
+        assert_eq!(
+            hinfo.to_bytes().unwrap_err().to_string(),
+            "HINFO CPU of 256 bytes is longer than 255"
+        );
+        let longest = RecordData::TXT(vec![vec![b'a'; 255]]).to_bytes().unwrap();
+        assert_eq!(longest[0], 255);
+        assert_eq!(longest.len(), 256);
+    }
+}
@@ -0,0 +1,125 @@
+use std::error::Error;
+
+use crate::{
+    constants, cursor::Cursor, dns_header::DNSHeader, dns_record::DNSRecord,
+    parse_options::ParseOptions,
+};
+
+/// Parses the records of a message one at a time instead of collecting every section up front
+/// like `DNSPacket` does, for responses too large to hold at once, e.g. zone transfers.
+/// Answers come first, then authorities, then additionals.
+pub struct RecordStream<'a> {
+    cursor: Cursor<'a>,
+    remaining: usize,
+    options: ParseOptions,
+}
+
+impl<'a> RecordStream<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, Box<dyn Error>> {
+        RecordStream::with_options(data, ParseOptions::default())
+    }
+
+    pub fn with_options(data: &'a [u8], options: ParseOptions) -> Result<Self, Box<dyn Error>> {
+        let mut cursor = Cursor::new(data, 0);
+        let header = DNSHeader::try_from(
+            cursor
+                .read_bytes(constants::DNS_HEADER_SIZE)
+                .map_err(|_| "Packet is shorter than a header")?,
+        )?;
+
+        // The questions aren't records, step over them
+        for _ in 0..header.num_questions() {
+            cursor.read_name(options.name_decoding)?;
+            cursor.read_bytes(constants::DNS_QUESTION_SIZE)?;
+        }
+
+        Ok(RecordStream {
+            cursor,
+            remaining: header.num_answers() as usize
+                + header.num_authorities() as usize
+                + header.num_additionals() as usize,
+            options,
+        })
+    }
+
+    /// Where the next record starts in the message
+    pub fn position(&self) -> usize {
+        self.cursor.position()
+    }
+
+    /// The records the header announced that haven't been read yet
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
+impl Iterator for RecordStream<'_> {
+    type Item = Result<DNSRecord, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        match DNSRecord::read(&mut self.cursor, &self.options) {
+            Ok(record) => {
+                self.remaining -= 1;
+                Some(Ok(record))
+            }
+            Err(error) => {
+                // There's no telling where the next record starts after a broken one
+                self.remaining = 0;
+                Some(Err(error))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// A response to example.com A with two answers and one additional record
+    fn example_response() -> Vec<u8> {
+        let mut data = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 1];
+        data.extend([
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+        ]);
+        data.extend([0, 1, 0, 1]);
+        for last_octet in [80, 81, 82] {
+            data.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 192, 0, 2]);
+            data.push(last_octet);
+        }
+        data
+    }
+
+    #[test]
+    fn test_streams_every_record() {
+        let data = example_response();
+
+        let records: Vec<_> = RecordStream::new(&data)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].data().get_A_bytes(), Some([192, 0, 2, 82]));
+    }
+
+    #[test]
+    fn test_stops_after_truncated_record() {
+        let data = example_response();
+
+        let results: Vec<_> = RecordStream::new(&data[..data.len() - 2])
+            .unwrap()
+            .collect();
+
+        assert_eq!(results.len(), 3);
+        assert!(results[2].is_err());
+    }
+}
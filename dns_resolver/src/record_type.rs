@@ -1,6 +1,10 @@
-use std::error::Error;
+use std::{
+    error::Error,
+    hash::{Hash, Hasher},
+};
 
 #[derive(Debug, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u16)]
 pub enum RecordType {
     // Bunch more can be found here.. https://datatracker.ietf.org/doc/html/rfc1035#section-3.2.2
@@ -8,20 +12,193 @@ pub enum RecordType {
     A = 1,
     NS = 2,
     CNAME = 5,
-    NotImplemented,
+    SOA = 6,
+    // Arbitrary data, kept as raw RDATA
+    NULL = 10,
+    HINFO = 13,
+    MINFO = 14,
+    MX = 15,
+    TXT = 16,
+    // Responsible person https://datatracker.ietf.org/doc/html/rfc1183#section-2.2
+    RP = 17,
+    AFSDB = 18,
+    AAAA = 28,
+    // Key exchanger https://datatracker.ietf.org/doc/html/rfc2230
+    KX = 36,
+    // The EDNS pseudo-record https://datatracker.ietf.org/doc/html/rfc6891
+    OPT = 41,
+    // Delegation signer https://datatracker.ietf.org/doc/html/rfc4034#section-5
+    DS = 43,
+    // DNSSEC signatures and denial of existence
+    RRSIG = 46,
+    NSEC = 47,
+    NSEC3 = 50,
+    // Zone management, named but kept as raw RDATA
+    // https://datatracker.ietf.org/doc/html/rfc7477 and https://datatracker.ietf.org/doc/html/rfc8976
+    CSYNC = 62,
+    ZONEMD = 63,
+    // Transaction signatures https://datatracker.ietf.org/doc/html/rfc8945
+    TSIG = 250,
+    // https://datatracker.ietf.org/doc/html/rfc7553
+    URI = 256,
+    // Anything we don't parse keeps its type number around. `from_u16` only makes these for
+    // numbers without a name, and one built by hand, like `Unknown(16)`, still equals the named type.
+    Unknown(u16),
+}
+
+// Equality and hashing go by the type number, so the two ways of writing a type can't drift apart
+impl PartialEq for RecordType {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_u16() == other.as_u16()
+    }
+}
+
+impl Eq for RecordType {}
+
+impl Hash for RecordType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_u16().hash(state);
+    }
+}
+
+impl From<RecordType> for u16 {
+    fn from(value: RecordType) -> Self {
+        match value {
+            RecordType::A => 1,
+            RecordType::NS => 2,
+            RecordType::CNAME => 5,
+            RecordType::SOA => 6,
+            RecordType::NULL => 10,
+            RecordType::HINFO => 13,
+            RecordType::MINFO => 14,
+            RecordType::MX => 15,
+            RecordType::TXT => 16,
+            RecordType::RP => 17,
+            RecordType::AFSDB => 18,
+            RecordType::AAAA => 28,
+            RecordType::KX => 36,
+            RecordType::OPT => 41,
+            RecordType::DS => 43,
+            RecordType::RRSIG => 46,
+            RecordType::NSEC => 47,
+            RecordType::NSEC3 => 50,
+            RecordType::CSYNC => 62,
+            RecordType::ZONEMD => 63,
+            RecordType::TSIG => 250,
+            RecordType::URI => 256,
+            RecordType::Unknown(value) => value,
+        }
+    }
 }
 
 impl TryFrom<u16> for RecordType {
     type Error = Box<dyn Error>;
 
     fn try_from(value: u16) -> Result<Self, Self::Error> {
-        let record = match value {
+        Ok(RecordType::from_u16(value))
+    }
+}
+
+impl RecordType {
+    /// The type number as it goes out on the wire.
+    pub fn as_u16(self) -> u16 {
+        self.into()
+    }
+
+    /// The type for a number off the wire, `Unknown` for the ones we don't name.
+    pub fn from_u16(value: u16) -> Self {
+        match value {
             1 => RecordType::A,
             2 => RecordType::NS,
             5 => RecordType::CNAME,
-            _ => RecordType::NotImplemented,
+            6 => RecordType::SOA,
+            10 => RecordType::NULL,
+            13 => RecordType::HINFO,
+            14 => RecordType::MINFO,
+            15 => RecordType::MX,
+            16 => RecordType::TXT,
+            17 => RecordType::RP,
+            18 => RecordType::AFSDB,
+            28 => RecordType::AAAA,
+            36 => RecordType::KX,
+            41 => RecordType::OPT,
+            43 => RecordType::DS,
+            46 => RecordType::RRSIG,
+            47 => RecordType::NSEC,
+            50 => RecordType::NSEC3,
+            62 => RecordType::CSYNC,
+            63 => RecordType::ZONEMD,
+            250 => RecordType::TSIG,
+            256 => RecordType::URI,
+            _ => RecordType::Unknown(value),
+        }
+    }
+
+    /// The mnemonic as written in zone files, `TYPE<n>` for unknown types as in
+    /// https://datatracker.ietf.org/doc/html/rfc3597#section-5
+    pub fn name(self) -> String {
+        let name = match RecordType::from_u16(self.as_u16()) {
+            RecordType::A => "A",
+            RecordType::NS => "NS",
+            RecordType::CNAME => "CNAME",
+            RecordType::SOA => "SOA",
+            RecordType::NULL => "NULL",
+            RecordType::HINFO => "HINFO",
+            RecordType::MINFO => "MINFO",
+            RecordType::MX => "MX",
+            RecordType::TXT => "TXT",
+            RecordType::RP => "RP",
+            RecordType::AFSDB => "AFSDB",
+            RecordType::AAAA => "AAAA",
+            RecordType::KX => "KX",
+            RecordType::OPT => "OPT",
+            RecordType::DS => "DS",
+            RecordType::RRSIG => "RRSIG",
+            RecordType::NSEC => "NSEC",
+            RecordType::NSEC3 => "NSEC3",
+            RecordType::CSYNC => "CSYNC",
+            RecordType::ZONEMD => "ZONEMD",
+            RecordType::TSIG => "TSIG",
+            RecordType::URI => "URI",
+            RecordType::Unknown(value) => return format!("TYPE{}", value),
         };
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_type_numbers() {
+        for (record_type, value, name) in [
+            (RecordType::A, 1, "A"),
+            (RecordType::MX, 15, "MX"),
+            (RecordType::AAAA, 28, "AAAA"),
+            (RecordType::URI, 256, "URI"),
+            (RecordType::Unknown(99), 99, "TYPE99"),
+        ] {
+            assert_eq!(record_type.as_u16(), value);
+            assert_eq!(RecordType::from_u16(value), record_type);
+            assert_eq!(record_type.name(), name);
+        }
+    }
+
+    #[test]
+    fn test_unknown_with_a_named_number_is_the_named_type() {
+        let by_hand = RecordType::Unknown(16);
+
+        assert_eq!(by_hand, RecordType::TXT);
+        assert_eq!(by_hand.name(), "TXT");
+        let types: std::collections::HashSet<_> = [by_hand, RecordType::TXT].into();
+        assert_eq!(types.len(), 1);
+    }
 
-        Ok(record)
+    #[test]
+    fn test_unknown_round_trips() {
+        let record_type = RecordType::from_u16(65280);
+        assert_eq!(record_type, RecordType::Unknown(65280));
+        assert_eq!(RecordType::from_u16(record_type.as_u16()), record_type);
     }
 }
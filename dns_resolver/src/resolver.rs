@@ -0,0 +1,3475 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    sync::{atomic::AtomicBool, mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+#[cfg(feature = "doh")]
+use crate::doh;
+#[cfg(feature = "socks5")]
+use crate::socks5;
+use crate::{
+    address_filter::AddressFilter,
+    cache::{Cache, CachedEntry},
+    check_cancelled, check_name_limits,
+    class::Class,
+    constants,
+    dns_packet::{self, DNSPacket},
+    dns_record::DNSRecord,
+    edns,
+    error::DnsError,
+    get_answer, get_cname, get_glue, get_name_server, get_referral_zone, in_bailiwick,
+    parse_options::{NameDecoding, ParseOptions},
+    query_builder::{QueryBuilder, QueryFlags},
+    randomize_case,
+    rate_limit::TokenBucket,
+    record_data::{RecordData, SoaData},
+    record_type::RecordType,
+    semaphore::Semaphore,
+    send_query,
+    single_flight::SingleFlight,
+    time_left,
+    transport::{TcpConnection, Transport},
+    validate_name,
+};
+
+/// Which address families `Resolver::resolve_addrs` asks for, and in what order they are returned.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum AddressFamilyPreference {
+    #[default]
+    V4First,
+    V6First,
+    V4Only,
+    V6Only,
+}
+
+impl AddressFamilyPreference {
+    fn record_types(&self) -> &'static [RecordType] {
+        match self {
+            AddressFamilyPreference::V4First => &[RecordType::A, RecordType::AAAA],
+            AddressFamilyPreference::V6First => &[RecordType::AAAA, RecordType::A],
+            AddressFamilyPreference::V4Only => &[RecordType::A],
+            AddressFamilyPreference::V6Only => &[RecordType::AAAA],
+        }
+    }
+}
+
+/// A nameserver's name along with its addresses, as returned by `Resolver::nameservers`.
+pub type NameServer = (String, Vec<IpAddr>);
+
+/// What a server did with one of the queries sent while resolving a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceOutcome {
+    Answer,
+    /// Sent us on to the nameservers of a zone closer to the name
+    Referral,
+    Error(String),
+}
+
+/// One query sent while resolving a name, see `Resolver::trace`.
+#[derive(Debug, Clone)]
+pub struct TraceStep {
+    pub server: IpAddr,
+    pub domain_name: String,
+    pub record_type: RecordType,
+    /// The transport the response came over, None if there was no response
+    pub transport: Option<Transport>,
+    pub outcome: TraceOutcome,
+}
+
+/// A one-struct summary of a `Resolver::resolve_reported`, meant for logging.
+#[derive(Debug, Clone)]
+pub struct ResolutionReport {
+    /// The name that was resolved, after search domains and in punycode
+    pub domain_name: String,
+    pub record_type: RecordType,
+    /// The A and AAAA addresses in the answer
+    pub addresses: Vec<IpAddr>,
+    /// The lowest TTL among the answers, 0 if there were none
+    pub ttl: u32,
+    /// Whether the final response had the AA bit set, always false for cache hits
+    pub authoritative: bool,
+    pub elapsed: Duration,
+    /// The transport the final response came over, None for cache hits
+    pub transport: Option<Transport>,
+    /// Referrals followed on the way to the answer, including those for glueless name servers.
+    /// None when an identical lookup running at the same time did the resolving.
+    pub referrals: Option<usize>,
+    pub cache_hit: bool,
+}
+
+/// The records answering a `Resolver::lookup`.
+#[derive(Debug)]
+pub struct Lookup {
+    records: Vec<DNSRecord>,
+    record_type: RecordType,
+    stale: bool,
+    authoritative: bool,
+    cache_hit: bool,
+    referrals: Option<usize>,
+    transport: Option<Transport>,
+    elapsed: Duration,
+    trace: Vec<TraceStep>,
+    ascii_name: String,
+    unicode_name: String,
+}
+
+impl Lookup {
+    pub fn records(&self) -> &[DNSRecord] {
+        &self.records
+    }
+    /// Whether the records are an expired cache entry served because the upstream could not be reached
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+    /// Whether the final response had the AA bit set, never for answers from the cache
+    pub fn is_authoritative(&self) -> bool {
+        self.authoritative
+    }
+    /// Whether the answer came from the cache without sending any query
+    pub fn is_cache_hit(&self) -> bool {
+        self.cache_hit
+    }
+    /// Referrals followed on the way to the answer, including those for glueless name servers.
+    /// None when an identical lookup running at the same time did the resolving.
+    pub fn referrals(&self) -> Option<usize> {
+        self.referrals
+    }
+    /// The transport the final response came over. None for cache hits, and when an identical
+    /// lookup running at the same time did the resolving.
+    pub fn transport(&self) -> Option<Transport> {
+        self.transport
+    }
+    /// The addresses of the A and AAAA records, in order
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        dns_packet::addresses(&self.records)
+    }
+    /// Wall time from the start of the lookup to the answer, across all referrals
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+    /// Every query sent on the way to the answer, empty unless tracing is turned on
+    pub fn trace(&self) -> &[TraceStep] {
+        &self.trace
+    }
+    /// The queried name as it went out on the wire, with internationalized labels in punycode
+    pub fn ascii_name(&self) -> &str {
+        &self.ascii_name
+    }
+    /// The queried name for display, with punycode labels decoded
+    pub fn unicode_name(&self) -> &str {
+        &self.unicode_name
+    }
+    /// The owner name of the first record of the queried type, which is where the last CNAME
+    /// pointed when the queried name is an alias
+    pub fn owner_name(&self) -> Option<&str> {
+        self.records
+            .iter()
+            .find(|record| record.type_() == self.record_type)
+            .or(self.records.first())
+            .and_then(|record| std::str::from_utf8(record.name()).ok())
+    }
+}
+
+#[derive(Debug)]
+pub struct Resolver {
+    root_server: IpAddr,
+    port: u16,
+    timeout: Duration,
+    address_family_preference: AddressFamilyPreference,
+    serve_stale: bool,
+    case_randomization: bool,
+    parse_options: ParseOptions,
+    transport: Transport,
+    transport_chain: Vec<Transport>,
+    retries: u32,
+    retry_backoff: Duration,
+    retry_jitter: bool,
+    lenient: bool,
+    strict_names: bool,
+    trace: bool,
+    recursion_desired: bool,
+    minimal_responses: bool,
+    edns_cookies: bool,
+    edns_padding: Option<usize>,
+    query_id: Option<u16>,
+    max_cname_hops: usize,
+    search_domains: Vec<String>,
+    ndots: usize,
+    rate_limit: Option<Mutex<TokenBucket>>,
+    inflight_limit: Option<Semaphore>,
+    address_filter: Option<AddressFilter>,
+    #[cfg(feature = "socks5")]
+    socks5_proxy: Option<SocketAddr>,
+    #[cfg(feature = "doh")]
+    doh_url: Option<String>,
+    #[cfg(feature = "doh")]
+    doh_agent: ureq::Agent,
+    bind_device: Option<String>,
+    client_cookie: [u8; constants::CLIENT_COOKIE_SIZE],
+    // The last cookie each server handed us, sent back on every later query to it
+    server_cookies: Mutex<HashMap<IpAddr, Vec<u8>>>,
+    cache: Mutex<Cache>,
+    // Glue from referrals, only ever used to find nameservers. It is kept apart from `cache`
+    // because it isn't authoritative data and must not be handed out as an answer.
+    glue: Mutex<Cache>,
+    // Lookups being resolved right now, which identical lookups wait for instead of querying again
+    in_flight: SingleFlight<(String, RecordType), (Vec<DNSRecord>, bool)>,
+    // Bound on the first query and reused for every query after that
+    socket: Mutex<Option<UdpSocket>>,
+    // TCP connections are kept open per server and reused for later queries
+    connections: Mutex<HashMap<SocketAddr, TcpConnection<TcpStream>>>,
+}
+
+fn verify_question_case(packet: &DNSPacket, query_name: &str) -> Result<(), Box<dyn Error>> {
+    match packet.questions().first() {
+        Some(question) if question.name() == query_name.as_bytes() => Ok(()),
+        _ => Err(format!(
+            "Response does not echo the query name {} with the same case",
+            query_name
+        )
+        .into()),
+    }
+}
+
+/// Every query asks a single question, which a response has to echo exactly once.
+/// Referrals followed on the way to the answer, including those for glueless name servers
+fn count_referrals(trace: &[TraceStep]) -> usize {
+    trace
+        .iter()
+        .filter(|step| matches!(step.outcome, TraceOutcome::Referral))
+        .count()
+}
+
+fn verify_question_count(packet: &DNSPacket) -> Result<(), Box<dyn Error>> {
+    match packet.header().num_questions() {
+        1 => Ok(()),
+        count => Err(format!("Response has {} questions to our query's 1", count).into()),
+    }
+}
+
+/// A socket on an ephemeral port of the same family as the server, tied to `device` if given.
+fn bind_udp_socket(server: SocketAddr, device: Option<&str>) -> Result<UdpSocket, Box<dyn Error>> {
+    let unspecified: IpAddr = match server {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((unspecified, 0))?;
+    if let Some(device) = device {
+        bind_device(&socket, device)?;
+    }
+    Ok(socket)
+}
+
+/// A TCP connection to the server going out of `device`.
+#[cfg(target_os = "linux")]
+fn connect_tcp_on_device(
+    server: SocketAddr,
+    device: &str,
+    timeout: Duration,
+) -> Result<TcpStream, Box<dyn Error>> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(server),
+        socket2::Type::STREAM,
+        None,
+    )?;
+    bind_device(&socket, device)?;
+    socket.connect_timeout(&server.into(), timeout)?;
+    Ok(socket.into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_tcp_on_device(
+    _server: SocketAddr,
+    device: &str,
+    _timeout: Duration,
+) -> Result<TcpStream, Box<dyn Error>> {
+    Err(format!("Can't bind to device {}: only supported on Linux", device).into())
+}
+
+/// SO_BINDTODEVICE, which only Linux has.
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &impl std::os::fd::AsFd, device: &str) -> Result<(), Box<dyn Error>> {
+    socket2::SockRef::from(socket)
+        .bind_device(Some(device.as_bytes()))
+        .map_err(|error| format!("Can't bind to device {}: {}", device, error).into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device<S>(_socket: &S, device: &str) -> Result<(), Box<dyn Error>> {
+    Err(format!("Can't bind to device {}: only supported on Linux", device).into())
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self {
+            root_server: constants::ROOT_NAME_SERVER.into(),
+            port: constants::DNS_PORT,
+            timeout: Duration::from_secs(5),
+            address_family_preference: AddressFamilyPreference::default(),
+            serve_stale: false,
+            case_randomization: false,
+            parse_options: ParseOptions::default(),
+            transport: Transport::default(),
+            transport_chain: vec![],
+            retries: 0,
+            retry_backoff: Duration::from_millis(100),
+            retry_jitter: false,
+            lenient: false,
+            strict_names: false,
+            trace: false,
+            recursion_desired: false,
+            minimal_responses: false,
+            edns_cookies: false,
+            edns_padding: None,
+            query_id: None,
+            max_cname_hops: 16,
+            search_domains: vec![],
+            ndots: 1,
+            rate_limit: None,
+            inflight_limit: None,
+            address_filter: None,
+            #[cfg(feature = "socks5")]
+            socks5_proxy: None,
+            #[cfg(feature = "doh")]
+            doh_url: None,
+            #[cfg(feature = "doh")]
+            doh_agent: ureq::Agent::new(),
+            bind_device: None,
+            client_cookie: rand::thread_rng().gen(),
+            server_cookies: Mutex::new(HashMap::new()),
+            cache: Mutex::new(Cache::new()),
+            glue: Mutex::new(Cache::new()),
+            in_flight: SingleFlight::new(),
+            socket: Mutex::new(None),
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends every query to a recursive resolver, like the one the system is configured with,
+    /// which answers in a single round trip instead of us walking down from the root.
+    pub fn recursive(server: IpAddr) -> Self {
+        Self::new().root_server(server).recursion_desired(true)
+    }
+
+    pub fn root_server(mut self, root_server: impl Into<IpAddr>) -> Self {
+        self.root_server = root_server.into();
+        self
+    }
+
+    /// The port every name server is queried on, like `dig -p`.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn address_family_preference(mut self, preference: AddressFamilyPreference) -> Self {
+        self.address_family_preference = preference;
+        self
+    }
+
+    /// Serve expired cache entries when the upstream fails, as described in RFC 8767.
+    pub fn serve_stale(mut self, serve_stale: bool) -> Self {
+        self.serve_stale = serve_stale;
+        self
+    }
+
+    /// Randomize the case of the query name (DNS 0x20) and require responses to echo it exactly,
+    /// which makes spoofed responses harder to get accepted.
+    pub fn case_randomization(mut self, case_randomization: bool) -> Self {
+        self.case_randomization = case_randomization;
+        self
+    }
+
+    /// How bytes in names that aren't valid UTF-8 are handled when parsing responses.
+    pub fn name_decoding(mut self, name_decoding: NameDecoding) -> Self {
+        self.parse_options = self.parse_options.name_decoding(name_decoding);
+        self
+    }
+
+    /// Reject responses larger than this many bytes, which bounds the memory a TCP response can
+    /// take. EDNS never advertises more than this either.
+    pub fn max_response_size(mut self, max_response_size: usize) -> Self {
+        self.parse_options = self.parse_options.max_response_size(max_response_size);
+        self
+    }
+
+    /// How queries are sent. With `Transport::Tcp` every query goes over TCP, and UDP is never used.
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Try every query over these transports in order, moving on to the next one when a
+    /// transport fails or hands back a truncated response, e.g. `[Udp, Tcp]` for networks that
+    /// drop UDP. The attempt's time is split evenly between them. Overrides `transport`.
+    pub fn transport_chain(mut self, transports: Vec<Transport>) -> Self {
+        self.transport_chain = transports;
+        self
+    }
+
+    /// Set RD on every query, asking the server to resolve the name for us. A server that
+    /// answers without RA and without an answer fails with `DnsError::RecursionNotAvailable`.
+    pub fn recursion_desired(mut self, recursion_desired: bool) -> Self {
+        self.recursion_desired = recursion_desired;
+        self
+    }
+
+    /// Resend a query that failed or timed out up to this many times, splitting the time left
+    /// between the attempts.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// How long to wait before the first retry, doubling for every retry after that.
+    pub fn retry_backoff(mut self, retry_backoff: Duration) -> Self {
+        self.retry_backoff = retry_backoff;
+        self
+    }
+
+    /// Randomize each backoff by up to 50% either way, so that clients which failed together
+    /// don't all retry at the same moment.
+    pub fn retry_jitter(mut self, retry_jitter: bool) -> Self {
+        self.retry_jitter = retry_jitter;
+        self
+    }
+
+    /// Also accept answers a server put in the authority section, as long as the name is within
+    /// the zone the server was delegated, and responses that don't echo exactly one question.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Refuse to look up names that aren't hostnames, see `validate_name`. Off by default, since
+    /// wildcards, classless reverse names and escaped labels are all fine in DNS. Names that
+    /// break the limits of the wire format are always refused.
+    pub fn strict_names(mut self, strict_names: bool) -> Self {
+        self.strict_names = strict_names;
+        self
+    }
+
+    /// Record every query sent during a lookup, available from `Lookup::trace`.
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Only keep the answer section of the final response. Authorities and additionals are still
+    /// used to follow referrals along the way, but dropped before the packet is handed back.
+    pub fn minimal_responses(mut self, minimal_responses: bool) -> Self {
+        self.minimal_responses = minimal_responses;
+        self
+    }
+
+    /// Send EDNS cookies (RFC 7873), which some servers require to fend off spoofed queries.
+    pub fn edns_cookies(mut self, edns_cookies: bool) -> Self {
+        self.edns_cookies = edns_cookies;
+        self
+    }
+
+    /// Pad every query to a multiple of this many bytes (RFC 7830), e.g. 128 as RFC 8467
+    /// recommends. Mostly useful where queries are encrypted, `None` turns padding off.
+    pub fn edns_padding(mut self, block_size: Option<usize>) -> Self {
+        self.edns_padding = block_size;
+        self
+    }
+
+    /// Send every query with this ID instead of a random one, which makes the queries on the wire
+    /// reproducible, e.g. for golden files. Random IDs are part of what keeps spoofed responses
+    /// out, so leave this off anywhere else.
+    pub fn query_id(mut self, query_id: u16) -> Self {
+        self.query_id = Some(query_id);
+        self
+    }
+
+    /// How many CNAMEs to follow for a single lookup before failing with `DnsError::CnameLoop`.
+    pub fn max_cname_hops(mut self, max_cname_hops: usize) -> Self {
+        self.max_cname_hops = max_cname_hops;
+        self
+    }
+
+    /// Domains to try unqualified names in, like `search` in resolv.conf: looking up "myhost"
+    /// with "corp.example.com" also tries "myhost.corp.example.com". Names ending in a dot are
+    /// never searched.
+    pub fn search_domains(mut self, search_domains: Vec<String>) -> Self {
+        self.search_domains = search_domains;
+        self
+    }
+
+    /// Names with at least this many dots are tried as they are before the search domains,
+    /// names with fewer only after them. Defaults to 1, like resolv.conf.
+    pub fn ndots(mut self, ndots: usize) -> Self {
+        self.ndots = ndots;
+        self
+    }
+
+    /// Send at most this many queries a second, waiting for the next free slot when lookups
+    /// come in faster than that. A query that would have to wait past its deadline fails.
+    /// Zero turns the limit off.
+    pub fn queries_per_second(mut self, queries_per_second: u32) -> Self {
+        self.rate_limit = match queries_per_second {
+            0 => None,
+            rate => Some(Mutex::new(TokenBucket::new(rate))),
+        };
+        self
+    }
+
+    /// Have at most this many queries waiting for a response at any time, across every lookup
+    /// sharing the resolver. Zero turns the limit off.
+    pub fn max_inflight(mut self, max_inflight: usize) -> Self {
+        self.inflight_limit = match max_inflight {
+            0 => None,
+            permits => Some(Semaphore::new(permits)),
+        };
+        self
+    }
+
+    /// Fail lookups with `DnsError::DeniedAddress` when any A or AAAA answer is an address
+    /// the filter doesn't allow, e.g. `AddressFilter::rebinding_protection()`.
+    pub fn address_filter(mut self, address_filter: AddressFilter) -> Self {
+        self.address_filter = Some(address_filter);
+        self
+    }
+
+    /// Send every query over TCP through this SOCKS5 proxy, for networks where DNS can't
+    /// get out any other way. UDP is never used while a proxy is set.
+    #[cfg(feature = "socks5")]
+    pub fn socks5_proxy(mut self, proxy: SocketAddr) -> Self {
+        self.socks5_proxy = Some(proxy);
+        self
+    }
+
+    /// Where `Transport::Doh` sends queries, e.g. `https://dns.example/dns-query`. Every query
+    /// goes to this URL rather than the server it was meant for, so it should be a recursive
+    /// resolver.
+    #[cfg(feature = "doh")]
+    pub fn doh_url(mut self, url: String) -> Self {
+        self.doh_url = Some(url);
+        self
+    }
+
+    /// Send queries out of this network interface only (SO_BINDTODEVICE), e.g. for policy
+    /// routing. Only Linux supports this, everywhere else every query fails.
+    pub fn bind_device(mut self, device: String) -> Self {
+        self.bind_device = Some(device);
+        self
+    }
+
+    pub fn lookup(
+        &self,
+        domain_name: &str,
+        record_type: RecordType,
+    ) -> Result<Lookup, Box<dyn Error>> {
+        self.lookup_with_class(domain_name, record_type, Class::In)
+    }
+
+    /// Like `lookup`, for a class other than IN, e.g. `version.bind` in CH. Answers outside of IN
+    /// are never cached.
+    pub fn lookup_with_class(
+        &self,
+        domain_name: &str,
+        record_type: RecordType,
+        class: Class,
+    ) -> Result<Lookup, Box<dyn Error>> {
+        let mut candidates = self.search_candidates(domain_name).into_iter().peekable();
+        while let Some(candidate) = candidates.next() {
+            match self.lookup_name(&candidate, record_type, class) {
+                // A name that doesn't exist, or lacks the type, may still exist in the next domain
+                Err(error)
+                    if candidates.peek().is_some()
+                        && matches!(
+                            error.downcast_ref::<DnsError>(),
+                            Some(DnsError::NxDomain(_) | DnsError::NoData(_))
+                        ) => {}
+                result => return result,
+            }
+        }
+        unreachable!("there is always at least the name itself to try")
+    }
+
+    /// The names to try for `domain_name` in order, see `search_domains`.
+    fn search_candidates(&self, domain_name: &str) -> Vec<String> {
+        // A fully qualified name is never searched, and goes out without its trailing dot
+        if let Some(name) = domain_name
+            .strip_suffix('.')
+            .filter(|name| !name.ends_with('\\'))
+        {
+            return vec![name.to_string()];
+        }
+        if self.search_domains.is_empty() {
+            return vec![domain_name.to_string()];
+        }
+        let searched = self
+            .search_domains
+            .iter()
+            .map(|domain| format!("{}.{}", domain_name, domain.trim_matches('.')));
+
+        match domain_name.matches('.').count() >= self.ndots {
+            true => std::iter::once(domain_name.to_string())
+                .chain(searched)
+                .collect(),
+            false => searched
+                .chain(std::iter::once(domain_name.to_string()))
+                .collect(),
+        }
+    }
+
+    fn lookup_name(
+        &self,
+        domain_name: &str,
+        record_type: RecordType,
+        class: Class,
+    ) -> Result<Lookup, Box<dyn Error>> {
+        let start = Instant::now();
+        // Internationalized names only go out in their punycode form
+        let ascii_name = match domain_name.is_ascii() {
+            true => domain_name.to_string(),
+            false => idna::domain_to_ascii(domain_name)
+                .map_err(|_| format!("{} is not a valid internationalized name", domain_name))?,
+        };
+        match self.strict_names {
+            true => validate_name(&ascii_name)?,
+            false => check_name_limits(&ascii_name)?,
+        }
+        let unicode_name = idna::domain_to_unicode(&ascii_name).0;
+        let lookup = |records, trace: Vec<TraceStep>| Lookup {
+            records,
+            record_type,
+            stale: false,
+            authoritative: false,
+            cache_hit: false,
+            referrals: Some(count_referrals(&trace)),
+            transport: trace.last().and_then(|step| step.transport),
+            elapsed: start.elapsed(),
+            trace: if self.trace { trace } else { vec![] },
+            ascii_name: ascii_name.clone(),
+            unicode_name: unicode_name.clone(),
+        };
+
+        let deadline = start + self.timeout;
+        let mut trace = vec![];
+        // The cache and the in-flight queries are keyed on IN answers only
+        if class != Class::In {
+            let packet =
+                self.resolve_traced(&ascii_name, record_type, class, deadline, None, &mut trace)?;
+            return Ok(Lookup {
+                authoritative: packet.header().flags().aa,
+                ..lookup(packet.answers().to_vec(), trace)
+            });
+        }
+
+        if let Some(entry) = self.cache.lock().unwrap().get(&ascii_name, record_type) {
+            if !entry.is_expired() {
+                return Ok(Lookup {
+                    cache_hit: true,
+                    ..lookup(entry.records().to_vec(), vec![])
+                });
+            }
+        }
+
+        let key = (ascii_name.to_ascii_lowercase(), record_type);
+        let resolved = self.in_flight.run(key, deadline, || {
+            self.resolve_traced(&ascii_name, record_type, class, deadline, None, &mut trace)
+                .map(|packet| (packet.answers().to_vec(), packet.header().flags().aa))
+        });
+        match resolved {
+            Ok((records, authoritative)) => {
+                // Only the lookup that did the resolving has a trace to count referrals in
+                let referrals = match trace.is_empty() {
+                    true => None,
+                    false => Some(count_referrals(&trace)),
+                };
+                let ttl = records.iter().map(|record| record.ttl()).min().unwrap_or(0);
+                self.cache.lock().unwrap().insert(
+                    &ascii_name,
+                    record_type,
+                    records.clone(),
+                    Duration::from_secs(ttl as u64),
+                );
+                Ok(Lookup {
+                    authoritative,
+                    referrals,
+                    ..lookup(records, trace)
+                })
+            }
+            Err(error) => {
+                let cache = self.cache.lock().unwrap();
+                match cache.get(&ascii_name, record_type) {
+                    Some(entry) if self.serve_stale => Ok(Lookup {
+                        stale: true,
+                        ..lookup(entry.records().to_vec(), trace)
+                    }),
+                    _ => Err(error),
+                }
+            }
+        }
+    }
+
+    /// Drops every cached answer, e.g. after a record was changed upstream.
+    pub fn cache_clear(&self) {
+        self.cache.lock().unwrap().clear();
+        self.glue.lock().unwrap().clear();
+    }
+
+    /// The number of cached answers, expired ones included.
+    pub fn cache_len(&self) -> usize {
+        self.cache.lock().unwrap().len()
+    }
+
+    /// The cached answer for the name and type, even if it has expired.
+    pub fn cache_entry(&self, domain_name: &str, record_type: RecordType) -> Option<CachedEntry> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(domain_name, record_type)
+            .cloned()
+    }
+
+    /// Like `lookup`, but hands back the whole final response instead of just its answers.
+    /// This skips the cache.
+    pub fn lookup_packet(
+        &self,
+        domain_name: &str,
+        record_type: RecordType,
+    ) -> Result<DNSPacket, Box<dyn Error>> {
+        self.resolve_iteratively(domain_name, record_type, Instant::now() + self.timeout)
+    }
+
+    /// Looks up several record types for the same name at once, grouping the records by type.
+    /// A type the name has no records of maps to an empty list.
+    pub fn lookup_types(
+        &self,
+        domain_name: &str,
+        record_types: &[RecordType],
+    ) -> Result<HashMap<RecordType, Vec<RecordData>>, Box<dyn Error>> {
+        let mut records = HashMap::new();
+
+        for record_type in record_types {
+            let data = match self.lookup(domain_name, *record_type) {
+                Ok(lookup) => lookup
+                    .records()
+                    .iter()
+                    .filter(|record| record.type_() == *record_type)
+                    .map(|record| record.data().clone())
+                    .collect(),
+                Err(error) => match error.downcast_ref::<DnsError>() {
+                    Some(DnsError::NoData(_)) => vec![],
+                    _ => return Err(error),
+                },
+            };
+            records.insert(*record_type, data);
+        }
+
+        Ok(records)
+    }
+
+    pub fn resolve_addrs(&self, domain_name: &str) -> Result<Vec<IpAddr>, Box<dyn Error>> {
+        self.resolve_addrs_before(domain_name, Instant::now() + self.timeout)
+    }
+
+    fn resolve_addrs_before(
+        &self,
+        domain_name: &str,
+        deadline: Instant,
+    ) -> Result<Vec<IpAddr>, Box<dyn Error>> {
+        let mut addrs = vec![];
+        let mut last_error = None;
+
+        for record_type in self.address_family_preference.record_types() {
+            // A name without records for one of the families is fine as long as the other one has some
+            match self.resolve_iteratively(domain_name, *record_type, deadline) {
+                Ok(packet) => {
+                    addrs.extend(
+                        packet
+                            .answers()
+                            .iter()
+                            .filter_map(|record| match record.data() {
+                                RecordData::A(ip) => Some(IpAddr::V4(*ip)),
+                                RecordData::AAAA(ip) => Some(IpAddr::V6(*ip)),
+                                _ => None,
+                            }),
+                    )
+                }
+                Err(error) => last_error = Some(error),
+            }
+        }
+
+        match last_error {
+            Some(error) if addrs.is_empty() => Err(error),
+            _ => Ok(addrs),
+        }
+    }
+
+    /// Looks up every address family at the same time and returns the first lookup that
+    /// succeeds, without waiting for the others. Only fails if all of them do, with the error
+    /// of the last one to fail.
+    pub fn lookup_first_address(
+        self: &Arc<Self>,
+        domain_name: &str,
+    ) -> Result<Lookup, Box<dyn Error>> {
+        let record_types = self.address_family_preference.record_types();
+        let (sender, receiver) = mpsc::channel();
+
+        for record_type in record_types {
+            let resolver = Arc::clone(self);
+            let sender = sender.clone();
+            let domain_name = domain_name.to_string();
+            // Errors can't cross threads as they are, so only their message comes back
+            thread::spawn(move || {
+                let result = resolver
+                    .lookup(&domain_name, *record_type)
+                    .map_err(|error| error.to_string());
+                let _ = sender.send(result);
+            });
+        }
+
+        let mut last_error = String::new();
+        for result in receiver.iter().take(record_types.len()) {
+            match result {
+                Ok(lookup) => return Ok(lookup),
+                Err(error) => last_error = error,
+            }
+        }
+        Err(last_error.into())
+    }
+
+    pub fn resolve_socket_addrs(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<Vec<SocketAddr>, Box<dyn Error>> {
+        // Like std::net::ToSocketAddrs, an IP literal is used as is without any lookup
+        let addrs = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![ip],
+            Err(_) => self.resolve_addrs(host)?,
+        };
+
+        Ok(addrs
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+
+    /// The authoritative nameservers of a zone with their addresses, taken from the glue
+    /// records where the server sent some and resolved separately otherwise.
+    pub fn nameservers(&self, zone: &str) -> Result<Vec<NameServer>, Box<dyn Error>> {
+        let deadline = Instant::now() + self.timeout;
+        let packet = self.resolve_iteratively(zone, RecordType::NS, deadline)?;
+
+        let mut nameservers = vec![];
+        for record in packet.answers() {
+            let Some(name) = record
+                .data()
+                .get_NS()
+                .filter(|_| record.type_() == RecordType::NS)
+            else {
+                continue;
+            };
+
+            let glue: Vec<IpAddr> = packet
+                .additionals()
+                .iter()
+                // Glue for a nameserver outside the zone isn't the zone's to give
+                .filter(|_| in_bailiwick(name, zone))
+                .filter(|glue| glue.name().eq_ignore_ascii_case(name.as_bytes()))
+                .filter_map(|glue| match glue.data() {
+                    RecordData::A(ip) => Some(IpAddr::V4(*ip)),
+                    RecordData::AAAA(ip) => Some(IpAddr::V6(*ip)),
+                    _ => None,
+                })
+                .collect();
+            let addrs = match glue.is_empty() {
+                true => self.resolve_addrs_before(name, deadline)?,
+                false => glue,
+            };
+            nameservers.push((name.to_string(), addrs));
+        }
+
+        Ok(nameservers)
+    }
+
+    /// The zone the name belongs to and its SOA, found by querying SOA for the name and then
+    /// each of its parents in turn until one has it.
+    pub fn zone_of(&self, domain_name: &str) -> Result<(String, SoaData), Box<dyn Error>> {
+        let deadline = Instant::now() + self.timeout;
+        let mut name = domain_name.trim_end_matches('.');
+
+        loop {
+            match self.resolve_iteratively(name, RecordType::SOA, deadline) {
+                Ok(packet) => {
+                    if let Some((zone, soa)) = packet.answers().iter().find_map(|record| {
+                        let soa = record.data().get_SOA()?;
+                        Some((String::from_utf8_lossy(record.name()), soa))
+                    }) {
+                        return Ok((zone.into_owned(), soa.clone()));
+                    }
+                }
+                Err(error) => match error.downcast_ref::<DnsError>() {
+                    Some(DnsError::NxDomain(_) | DnsError::NoData(_)) => {}
+                    _ => return Err(error),
+                },
+            }
+
+            name = match name.split_once('.') {
+                Some((_, parent)) => parent,
+                None => return Err(format!("No zone found for {}", domain_name).into()),
+            };
+        }
+    }
+
+    /// Whether the name has any A or AAAA records. NXDOMAIN and NODATA are not errors here.
+    pub fn exists(&self, domain_name: &str) -> Result<bool, Box<dyn Error>> {
+        for record_type in [RecordType::A, RecordType::AAAA] {
+            match self.lookup(domain_name, record_type) {
+                Ok(lookup) => {
+                    if lookup.records().iter().any(|record| {
+                        matches!(record.data(), RecordData::A(_) | RecordData::AAAA(_))
+                    }) {
+                        return Ok(true);
+                    }
+                }
+                Err(error) => match error.downcast_ref::<DnsError>() {
+                    Some(DnsError::NxDomain(_)) => return Ok(false),
+                    Some(DnsError::NoData(_)) => continue,
+                    _ => return Err(error),
+                },
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Whether any of the name's A records is `expected`, e.g. to check that a DNS change has
+    /// gone through.
+    pub fn resolve_expect(
+        &self,
+        domain_name: &str,
+        expected: Ipv4Addr,
+    ) -> Result<bool, Box<dyn Error>> {
+        let lookup = self.lookup(domain_name, RecordType::A)?;
+
+        Ok(lookup
+            .records()
+            .iter()
+            .any(|record| record.data().get_A() == Some(&expected)))
+    }
+
+    /// The target the name is a CNAME for, without following it. `None` if the name exists but
+    /// isn't an alias.
+    pub fn lookup_cname(&self, domain_name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let lookup = match self.lookup(domain_name, RecordType::CNAME) {
+            Ok(lookup) => lookup,
+            Err(error) => match error.downcast_ref::<DnsError>() {
+                Some(DnsError::NoData(_)) => return Ok(None),
+                _ => return Err(error),
+            },
+        };
+
+        Ok(lookup
+            .records()
+            .iter()
+            .find(|record| record.type_() == RecordType::CNAME)
+            .and_then(|record| record.data().get_NS())
+            .map(str::to_string))
+    }
+
+    fn send_query(
+        &self,
+        server: SocketAddr,
+        domain_name: &str,
+        record_type: RecordType,
+        class: Class,
+        deadline: Instant,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<(DNSPacket, Transport), Box<dyn Error>> {
+        let query_name = match self.case_randomization {
+            true => randomize_case(domain_name),
+            false => domain_name.to_string(),
+        };
+
+        let query = self.build_query(server, &query_name, record_type, class)?;
+        let mut attempt = 0;
+        let (packet, transport) = loop {
+            check_cancelled(cancel)?;
+            self.wait_for_rate_limit(&query_name, deadline)?;
+            let attempts_left = self.retries - attempt + 1;
+            let attempt_deadline =
+                Instant::now() + time_left(deadline, &query_name)? / attempts_left;
+            // Running out of time waiting for a permit is a failed attempt like any other
+            let send =
+                || self.send_over_transports(server, &query_name, &query, attempt_deadline, cancel);
+            let result = match &self.inflight_limit {
+                Some(limit) => limit.acquire(attempt_deadline).and_then(|_permit| send()),
+                None => send(),
+            };
+
+            match result {
+                Ok(sent) => break sent,
+                Err(_) if attempt < self.retries => {
+                    let backoff = self.backoff(attempt, &mut rand::thread_rng());
+                    thread::sleep(backoff.min(time_left(deadline, &query_name)?));
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        };
+
+        if !self.lenient {
+            verify_question_count(&packet)?;
+        }
+        if self.case_randomization {
+            verify_question_case(&packet, &query_name)?;
+        }
+        if self.edns_cookies {
+            self.remember_server_cookie(server, &packet)?;
+        }
+        Ok((packet, transport))
+    }
+
+    fn wait_for_rate_limit(
+        &self,
+        domain_name: &str,
+        deadline: Instant,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(rate_limit) = &self.rate_limit else {
+            return Ok(());
+        };
+
+        let wait = rate_limit.lock().unwrap().take();
+        match time_left(deadline, domain_name) {
+            Ok(left) if wait <= left => {}
+            left => {
+                // The query won't go out, so it doesn't get to use up a token either
+                rate_limit.lock().unwrap().give_back();
+                left?;
+                return Err(format!(
+                    "Rate limit would hold the query for {} past its deadline",
+                    domain_name
+                )
+                .into());
+            }
+        }
+        thread::sleep(wait);
+        Ok(())
+    }
+
+    fn check_addresses(&self, domain_name: &str, packet: &DNSPacket) -> Result<(), DnsError> {
+        let Some(filter) = &self.address_filter else {
+            return Ok(());
+        };
+
+        let denied = packet.answers().iter().find_map(|record| {
+            match record.data() {
+                RecordData::A(ip) => Some(IpAddr::V4(*ip)),
+                RecordData::AAAA(ip) => Some(IpAddr::V6(*ip)),
+                _ => None,
+            }
+            .filter(|ip| !filter.allows(*ip))
+        });
+        match denied {
+            Some(address) => Err(DnsError::DeniedAddress {
+                name: domain_name.to_string(),
+                address,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn backoff(&self, attempt: u32, rng: &mut impl Rng) -> Duration {
+        let backoff = self.retry_backoff * 2u32.saturating_pow(attempt);
+        match self.retry_jitter {
+            true => backoff.mul_f64(rng.gen_range(0.5..=1.5)),
+            false => backoff,
+        }
+    }
+
+    fn build_query(
+        &self,
+        server: SocketAddr,
+        domain_name: &str,
+        record_type: RecordType,
+        class: Class,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let flags = match self.recursion_desired {
+            true => QueryFlags::RD,
+            false => QueryFlags::NONE,
+        };
+        let mut builder = QueryBuilder::new(domain_name, record_type)
+            .class(class)
+            .flags(flags);
+        if let Some(id) = self.query_id {
+            builder = builder.id(id);
+        }
+
+        if !self.edns_cookies && self.edns_padding.is_none() {
+            return builder.without_edns().build();
+        }
+
+        if self.edns_cookies {
+            let mut cookie = self.client_cookie.to_vec();
+            if let Some(server_cookie) = self.server_cookies.lock().unwrap().get(&server.ip()) {
+                cookie.extend(server_cookie);
+            }
+            builder = builder.edns_option(constants::EDNS_OPTION_COOKIE, cookie);
+        }
+        if let Some(block_size) = self.edns_padding {
+            builder = builder.edns_padding(block_size);
+        }
+        // Never advertise more than we can actually receive, or are willing to
+        let udp_size = self
+            .parse_options
+            .max_response_size
+            .map_or(constants::UDP_DNS_RESPONSE_SIZE, |max| {
+                max.min(constants::UDP_DNS_RESPONSE_SIZE)
+            });
+        builder.edns_udp_size(udp_size as u16).build()
+    }
+
+    fn remember_server_cookie(
+        &self,
+        server: SocketAddr,
+        packet: &DNSPacket,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(cookie) = edns::find_option(packet, constants::EDNS_OPTION_COOKIE)? else {
+            // Servers without cookie support just leave it out
+            return Ok(());
+        };
+
+        let (client_cookie, server_cookie) =
+            cookie.split_at(constants::CLIENT_COOKIE_SIZE.min(cookie.len()));
+        if client_cookie != self.client_cookie {
+            return Err(
+                format!("Response from {} does not carry our client cookie", server).into(),
+            );
+        }
+        // A server cookie is between 8 and 32 bytes long
+        if (8..=32).contains(&server_cookie.len()) {
+            self.server_cookies
+                .lock()
+                .unwrap()
+                .insert(server.ip(), server_cookie.to_vec());
+        }
+        Ok(())
+    }
+
+    fn send_udp_query(
+        &self,
+        server: SocketAddr,
+        domain_name: &str,
+        query: &[u8],
+        deadline: Instant,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<DNSPacket, Box<dyn Error>> {
+        // Another query holding the shared socket would make us wait for its response, so
+        // concurrent queries get a socket of their own
+        let Ok(mut socket) = self.socket.try_lock() else {
+            let socket = bind_udp_socket(server, self.bind_device.as_deref())?;
+            return send_query(
+                &socket,
+                server,
+                domain_name,
+                query,
+                deadline,
+                cancel,
+                &self.parse_options,
+            );
+        };
+        // A socket of the wrong family can't reach the server, e.g. an IPv6 recursive resolver
+        if let Some(bound) = &*socket {
+            if bound.local_addr()?.is_ipv4() != server.is_ipv4() {
+                *socket = None;
+            }
+        }
+        let socket = match &mut *socket {
+            Some(socket) => socket,
+            None => socket.insert(bind_udp_socket(server, self.bind_device.as_deref())?),
+        };
+
+        send_query(
+            socket,
+            server,
+            domain_name,
+            query,
+            deadline,
+            cancel,
+            &self.parse_options,
+        )
+    }
+
+    /// The transports a query is tried over, in order.
+    fn transports(&self) -> Vec<Transport> {
+        #[cfg(feature = "socks5")]
+        if self.socks5_proxy.is_some() {
+            return vec![Transport::Tcp];
+        }
+        match self.transport_chain.is_empty() {
+            true => vec![self.transport],
+            false => self.transport_chain.clone(),
+        }
+    }
+
+    fn send_over_transports(
+        &self,
+        server: SocketAddr,
+        domain_name: &str,
+        query: &[u8],
+        deadline: Instant,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<(DNSPacket, Transport), Box<dyn Error>> {
+        let transports = self.transports();
+        let mut result =
+            Err(format!("No transport to send the query for {} over", domain_name).into());
+        for (index, transport) in transports.iter().enumerate() {
+            let transports_left = (transports.len() - index) as u32;
+            let transport_deadline =
+                Instant::now() + time_left(deadline, domain_name)? / transports_left;
+            result = match transport {
+                Transport::Udp => {
+                    self.send_udp_query(server, domain_name, query, transport_deadline, cancel)
+                }
+                Transport::Tcp => {
+                    self.send_tcp_query(server, domain_name, query, transport_deadline)
+                }
+                #[cfg(feature = "doh")]
+                Transport::Doh => self.send_doh_query(domain_name, query, transport_deadline),
+            }
+            .map(|packet| (packet, *transport));
+            // The last transport's truncated response is still better than nothing
+            match &result {
+                Ok((packet, _)) if !packet.header().flags().tc => break,
+                _ => continue,
+            }
+        }
+        result
+    }
+
+    fn connect_tcp(
+        &self,
+        server: SocketAddr,
+        timeout: Duration,
+    ) -> Result<TcpStream, Box<dyn Error>> {
+        #[cfg(feature = "socks5")]
+        if let Some(proxy) = self.socks5_proxy {
+            return socks5::connect(proxy, server, timeout);
+        }
+        match &self.bind_device {
+            Some(device) => connect_tcp_on_device(server, device, timeout),
+            None => Ok(TcpStream::connect_timeout(&server, timeout)?),
+        }
+    }
+
+    #[cfg(feature = "doh")]
+    fn send_doh_query(
+        &self,
+        domain_name: &str,
+        query: &[u8],
+        deadline: Instant,
+    ) -> Result<DNSPacket, Box<dyn Error>> {
+        let url = self
+            .doh_url
+            .as_deref()
+            .ok_or("Transport::Doh needs a doh_url to send queries to")?;
+        let timeout = time_left(deadline, domain_name)?;
+        doh::query(&self.doh_agent, url, query, timeout, &self.parse_options)
+    }
+
+    fn send_tcp_query(
+        &self,
+        server: SocketAddr,
+        domain_name: &str,
+        query: &[u8],
+        deadline: Instant,
+    ) -> Result<DNSPacket, Box<dyn Error>> {
+        // The lock is only held to take the connection out, so queries run concurrently. One
+        // that finds the connection taken opens another.
+        let reused = self.connections.lock().unwrap().remove(&server);
+        let result = match reused {
+            Some(connection) => {
+                match self.query_tcp_connection(connection, domain_name, query, deadline) {
+                    // Servers close connections that sat idle, so a pooled one failing to
+                    // carry the query gets one more try on a new connection
+                    Err(error) if error.downcast_ref::<std::io::Error>().is_some() => {
+                        let connection = self.open_tcp_connection(server, domain_name, deadline)?;
+                        self.query_tcp_connection(connection, domain_name, query, deadline)
+                    }
+                    result => result,
+                }
+            }
+            None => {
+                let connection = self.open_tcp_connection(server, domain_name, deadline)?;
+                self.query_tcp_connection(connection, domain_name, query, deadline)
+            }
+        };
+        let (packet, connection) = result?;
+
+        // Only a connection that just worked goes back for reuse
+        self.connections.lock().unwrap().insert(server, connection);
+        Ok(packet)
+    }
+
+    fn open_tcp_connection(
+        &self,
+        server: SocketAddr,
+        domain_name: &str,
+        deadline: Instant,
+    ) -> Result<TcpConnection<TcpStream>, Box<dyn Error>> {
+        let timeout = time_left(deadline, domain_name)?;
+        Ok(TcpConnection::new(self.connect_tcp(server, timeout)?))
+    }
+
+    fn query_tcp_connection(
+        &self,
+        mut connection: TcpConnection<TcpStream>,
+        domain_name: &str,
+        query: &[u8],
+        deadline: Instant,
+    ) -> Result<(DNSPacket, TcpConnection<TcpStream>), Box<dyn Error>> {
+        let timeout = time_left(deadline, domain_name)?;
+        connection.stream().set_read_timeout(Some(timeout))?;
+        connection.stream().set_write_timeout(Some(timeout))?;
+
+        let packet = connection.query(query, &self.parse_options)?;
+        Ok((packet, connection))
+    }
+
+    /// Resolves the name with this resolver's settings and hands `select` the answers to pick
+    /// the one to return, e.g. the record with the longest TTL. Fails if `select` picks none.
+    /// This skips the cache.
+    pub fn resolve_with<F>(
+        &self,
+        domain_name: &str,
+        record_type: RecordType,
+        deadline: Instant,
+        select: F,
+    ) -> Result<DNSRecord, Box<dyn Error>>
+    where
+        F: Fn(&[DNSRecord]) -> Option<&DNSRecord>,
+    {
+        let packet = self.resolve_iteratively(domain_name, record_type, deadline)?;
+
+        select(packet.answers())
+            .cloned()
+            .ok_or_else(|| format!("No answer selected for {}", domain_name).into())
+    }
+
+    /// Resolves the name until it is done or `cancel` is set, e.g. when whoever asked for the
+    /// name went away, failing with `DnsError::Cancelled` in the latter case. Cancellation is
+    /// noticed between referrals and while waiting for UDP responses.
+    pub fn resolve_cancellable(
+        &self,
+        domain_name: &str,
+        record_type: RecordType,
+        cancel: &AtomicBool,
+    ) -> Result<DNSPacket, Box<dyn Error>> {
+        let deadline = Instant::now() + self.timeout;
+        self.resolve_traced(
+            domain_name,
+            record_type,
+            Class::In,
+            deadline,
+            Some(cancel),
+            &mut vec![],
+        )
+    }
+
+    /// Resolves the name with a deadline of its own instead of the resolver's `timeout`, for the
+    /// odd call that can't wait as long as the rest.
+    pub fn resolve_with_timeout(
+        &self,
+        domain_name: &str,
+        record_type: RecordType,
+        timeout: Duration,
+    ) -> Result<DNSPacket, Box<dyn Error>> {
+        self.resolve_iteratively(domain_name, record_type, Instant::now() + timeout)
+    }
+
+    /// Looks the name up like `lookup` and sums up how it went in a `ResolutionReport`.
+    pub fn resolve_reported(
+        &self,
+        domain_name: &str,
+        record_type: RecordType,
+    ) -> Result<ResolutionReport, Box<dyn Error>> {
+        let lookup = self.lookup(domain_name, record_type)?;
+        Ok(ResolutionReport {
+            domain_name: lookup.ascii_name().to_string(),
+            record_type,
+            addresses: lookup.addresses(),
+            ttl: lookup
+                .records()
+                .iter()
+                .map(|record| record.ttl())
+                .min()
+                .unwrap_or(0),
+            authoritative: lookup.is_authoritative(),
+            elapsed: lookup.elapsed(),
+            transport: lookup.transport(),
+            referrals: lookup.referrals(),
+            cache_hit: lookup.is_cache_hit(),
+        })
+    }
+
+    pub(crate) fn resolve_iteratively(
+        &self,
+        domain_name: &str,
+        record_type: RecordType,
+        deadline: Instant,
+    ) -> Result<DNSPacket, Box<dyn Error>> {
+        self.resolve_traced(
+            domain_name,
+            record_type,
+            Class::In,
+            deadline,
+            None,
+            &mut vec![],
+        )
+    }
+
+    /// An unexpired IPv4 address for a nameserver, preferring a cached answer over glue from an
+    /// earlier referral.
+    fn cached_address(&self, domain_name: &str) -> Option<IpAddr> {
+        let address = |cache: &Cache| {
+            cache
+                .get(domain_name, RecordType::A)
+                .filter(|entry| !entry.is_expired())?
+                .records()
+                .iter()
+                .find_map(|record| record.data().get_A())
+                .map(|ip| IpAddr::V4(*ip))
+        };
+        address(&self.cache.lock().unwrap()).or_else(|| address(&self.glue.lock().unwrap()))
+    }
+
+    fn resolve_traced(
+        &self,
+        domain_name: &str,
+        record_type: RecordType,
+        class: Class,
+        deadline: Instant,
+        cancel: Option<&AtomicBool>,
+        trace: &mut Vec<TraceStep>,
+    ) -> Result<DNSPacket, Box<dyn Error>> {
+        let mut domain_name = domain_name.to_string();
+        let mut name_server_ip = self.root_server;
+        // The zone the current server is authoritative for, as far as the referrals told us
+        let mut zone = String::new();
+        // Every name we have been sent to by a CNAME, starting with the one asked for
+        let mut chain = vec![domain_name.clone()];
+        // Nameservers without glue we already looked up, in case a later referral names them again
+        let mut name_server_ips: HashMap<String, IpAddr> = HashMap::new();
+
+        loop {
+            check_cancelled(cancel)?;
+            let server = SocketAddr::new(name_server_ip, self.port);
+            let sent = self.send_query(server, &domain_name, record_type, class, deadline, cancel);
+            let transport = sent.as_ref().ok().map(|(_, transport)| *transport);
+            let mut step = |outcome| {
+                trace.push(TraceStep {
+                    server: name_server_ip,
+                    domain_name: domain_name.clone(),
+                    record_type,
+                    transport,
+                    outcome,
+                });
+            };
+
+            let mut packet = match sent {
+                Ok((packet, _)) => packet,
+                Err(error) => {
+                    step(TraceOutcome::Error(error.to_string()));
+                    return Err(error);
+                }
+            };
+
+            if self.lenient && in_bailiwick(&domain_name, &zone) {
+                packet.promote_authority_answers(&domain_name, record_type);
+            }
+
+            if packet
+                .answers()
+                .iter()
+                .any(|record| record.type_() == record_type)
+            {
+                step(TraceOutcome::Answer);
+                self.check_addresses(&domain_name, &packet)?;
+                if self.minimal_responses {
+                    packet.drop_authorities_and_additionals();
+                }
+                return Ok(packet);
+            }
+
+            if self.recursion_desired && !packet.header().flags().ra {
+                let error = DnsError::RecursionNotAvailable(server.to_string());
+                step(TraceOutcome::Error(error.to_string()));
+                return Err(error.into());
+            }
+
+            // Alongside a CNAME, NS records only refer us on if they are for a zone holding the
+            // target. Servers often attach their own zone's NS set to an answer.
+            let cname = get_cname(&packet).map(str::to_string);
+            let referral = get_referral_zone(&packet).is_some_and(|referral_zone| {
+                cname
+                    .as_deref()
+                    .is_none_or(|target| in_bailiwick(target, referral_zone))
+            });
+
+            match referral {
+                true => step(TraceOutcome::Referral),
+                // A CNAME without a referral still answered part of the question
+                false if cname.is_some() => step(TraceOutcome::Answer),
+                false => {
+                    let error: Box<dyn Error> = match packet.header().rcode() {
+                        constants::RCODE_NAME_ERROR => {
+                            DnsError::NxDomain(domain_name.clone()).into()
+                        }
+                        constants::RCODE_NO_ERROR => DnsError::NoData(domain_name.clone()).into(),
+                        rcode => {
+                            format!("Server responded with RCODE {} for {}", rcode, domain_name)
+                                .into()
+                        }
+                    };
+                    step(TraceOutcome::Error(error.to_string()));
+                    return Err(error);
+                }
+            }
+
+            if let Some(target) = cname {
+                // From here on it is the CNAME target we are resolving, not the original name.
+                // If the server also handed us a referral for the target we follow it directly,
+                // otherwise we have to start over from the root.
+                domain_name = target;
+                chain.push(domain_name.clone());
+                if chain.len() - 1 > self.max_cname_hops {
+                    return Err(DnsError::CnameLoop { chain }.into());
+                }
+                if !referral {
+                    name_server_ip = self.root_server;
+                    zone = String::new();
+                    continue;
+                }
+            }
+
+            let glue = get_glue(&packet, &zone).cloned();
+            zone = get_referral_zone(&packet).unwrap_or_default().to_string();
+
+            if let Some(glue) = glue {
+                name_server_ip = IpAddr::V4(*glue.data().get_A().expect("glue is an A record"));
+                // Saves looking the nameserver up when a later referral comes without glue
+                let ttl = Duration::from_secs(glue.ttl() as u64);
+                let name = String::from_utf8_lossy(glue.name()).into_owned();
+                self.glue
+                    .lock()
+                    .unwrap()
+                    .insert(&name, RecordType::A, vec![glue], ttl);
+            } else {
+                let ns_domain = get_name_server(&packet).expect("referral checked above");
+                let key = ns_domain.to_ascii_lowercase();
+                let known = name_server_ips
+                    .get(&key)
+                    .copied()
+                    .or_else(|| self.cached_address(ns_domain));
+                name_server_ip = match known {
+                    Some(ip) => ip,
+                    None => {
+                        let packet = self.resolve_traced(
+                            ns_domain,
+                            RecordType::A,
+                            Class::In,
+                            deadline,
+                            cancel,
+                            trace,
+                        )?;
+                        match get_answer(&packet).map(|answer| answer.data()) {
+                            Some(RecordData::A(ip)) => IpAddr::V4(*ip),
+                            _ => return Err(format!("No A record found for {}", ns_domain).into()),
+                        }
+                    }
+                };
+                name_server_ips.insert(key, name_server_ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::encode_dns_name;
+
+    use super::*;
+
+    /// Answers every query on a local port with the given RCODE and answer records,
+    /// reporting where each query came from
+    fn mock_server(rcode: u8, answers: Vec<Vec<u8>>) -> (u16, mpsc::Receiver<SocketAddr>) {
+        mock_server_with_additionals(rcode, answers, vec![])
+    }
+
+    fn mock_server_with_additionals(
+        rcode: u8,
+        answers: Vec<Vec<u8>>,
+        additionals: Vec<Vec<u8>>,
+    ) -> (u16, mpsc::Receiver<SocketAddr>) {
+        scripted_server(vec![MockResponse {
+            rcode,
+            answers,
+            additionals,
+            ..Default::default()
+        }])
+    }
+
+    #[derive(Default)]
+    struct MockResponse {
+        // How long the server takes to answer
+        delay: Duration,
+        rcode: u8,
+        answers: Vec<Vec<u8>>,
+        authorities: Vec<Vec<u8>>,
+        additionals: Vec<Vec<u8>>,
+    }
+
+    /// Answers the n-th query with the n-th response, and every query after the script runs out
+    /// with the last one
+    fn scripted_server(responses: Vec<MockResponse>) -> (u16, mpsc::Receiver<SocketAddr>) {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            let mut script = responses.iter();
+            let mut current = None;
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                let _ = sender.send(peer);
+                current = script.next().or(current);
+                let MockResponse {
+                    delay,
+                    rcode,
+                    answers,
+                    authorities,
+                    additionals,
+                } = current.unwrap();
+                // Echo the query back as a response, which keeps the ID and question intact
+                let mut response = buffer[..length].to_vec();
+                response[2] |= 0b1000_0100;
+                response[3] = *rcode;
+                for (offset, section) in [(6, answers), (8, authorities), (10, additionals)] {
+                    response[offset..offset + 2]
+                        .copy_from_slice(&(section.len() as u16).to_be_bytes());
+                }
+                for record in answers.iter().chain(authorities).chain(additionals) {
+                    response.extend(record);
+                }
+                thread::sleep(*delay);
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+
+        (port, receiver)
+    }
+
+    /// An A record whose owner name points back at the question
+    fn a_record_bytes(ip: Ipv4Addr) -> Vec<u8> {
+        let mut bytes = vec![0xc0, 12, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4];
+        bytes.extend(ip.octets());
+        bytes
+    }
+
+    /// A CNAME record whose owner name points back at the question
+    fn cname_record_bytes(target: &str) -> Vec<u8> {
+        let target = encode_dns_name(target).unwrap();
+        let mut bytes = vec![0xc0, 12, 0, 5, 0, 1, 0, 0, 0x0e, 0x10];
+        bytes.extend((target.len() as u16).to_be_bytes());
+        bytes.extend(target);
+        bytes
+    }
+
+    /// The SOA record of `zone`, with a serial of 1
+    fn soa_record_bytes(zone: &str) -> Vec<u8> {
+        let mut rdata = encode_dns_name(&format!("ns1.{}", zone)).unwrap();
+        rdata.extend(encode_dns_name(&format!("hostmaster.{}", zone)).unwrap());
+        for field in [1u32, 7200, 3600, 1209600, 3600] {
+            rdata.extend(field.to_be_bytes());
+        }
+        let mut bytes = encode_dns_name(zone).unwrap();
+        bytes.extend([0, 6, 0, 1, 0, 0, 0x0e, 0x10]);
+        bytes.extend((rdata.len() as u16).to_be_bytes());
+        bytes.extend(rdata);
+        bytes
+    }
+
+    fn mock_resolver(port: u16) -> Resolver {
+        Resolver::new()
+            .root_server(Ipv4Addr::LOCALHOST)
+            .port(port)
+            .timeout(Duration::from_millis(500))
+    }
+
+    const DUAL_STACK_V4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const DUAL_STACK_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+
+    /// Answers A queries with 192.0.2.1 and AAAA queries with 2001:db8::1, reporting the type
+    /// of each query
+    fn dual_stack_server() -> (u16, mpsc::Receiver<RecordType>) {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                let query = DNSPacket::parse(&buffer[..length]).unwrap();
+                let record_type = query.questions()[0].type_();
+                let _ = sender.send(record_type);
+                let mut response = buffer[..length].to_vec();
+                response[2] |= 0b1000_0100;
+                response[7] = 1;
+                match record_type {
+                    RecordType::AAAA => {
+                        response.extend([0xc0, 12, 0, 28, 0, 1, 0, 0, 0x0e, 0x10, 0, 16]);
+                        response.extend(DUAL_STACK_V6.octets());
+                    }
+                    _ => response.extend(a_record_bytes(DUAL_STACK_V4)),
+                }
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        (port, receiver)
+    }
+
+    #[test]
+    fn test_v4_first_queries_both_families() {
+        let (port, queries) = dual_stack_server();
+        let resolver =
+            mock_resolver(port).address_family_preference(AddressFamilyPreference::V4First);
+
+        let addrs = resolver.resolve_addrs("example.com").unwrap();
+
+        assert_eq!(
+            addrs,
+            [IpAddr::from(DUAL_STACK_V4), IpAddr::from(DUAL_STACK_V6)]
+        );
+        assert_eq!(
+            queries.try_iter().collect::<Vec<_>>(),
+            [RecordType::A, RecordType::AAAA]
+        );
+    }
+
+    #[test]
+    fn test_v6_first_queries_both_families() {
+        let (port, queries) = dual_stack_server();
+        let resolver =
+            mock_resolver(port).address_family_preference(AddressFamilyPreference::V6First);
+
+        let addrs = resolver.resolve_addrs("example.com").unwrap();
+
+        assert_eq!(
+            addrs,
+            [IpAddr::from(DUAL_STACK_V6), IpAddr::from(DUAL_STACK_V4)]
+        );
+        assert_eq!(
+            queries.try_iter().collect::<Vec<_>>(),
+            [RecordType::AAAA, RecordType::A]
+        );
+    }
+
+    #[test]
+    fn test_v4_only_queries_a() {
+        let (port, queries) = dual_stack_server();
+        let resolver =
+            mock_resolver(port).address_family_preference(AddressFamilyPreference::V4Only);
+
+        let addrs = resolver.resolve_addrs("example.com").unwrap();
+
+        assert_eq!(addrs, [IpAddr::from(DUAL_STACK_V4)]);
+        assert_eq!(queries.try_iter().collect::<Vec<_>>(), [RecordType::A]);
+    }
+
+    #[test]
+    fn test_v6_only_queries_aaaa() {
+        let (port, queries) = dual_stack_server();
+        let resolver =
+            mock_resolver(port).address_family_preference(AddressFamilyPreference::V6Only);
+
+        let addrs = resolver.resolve_addrs("example.com").unwrap();
+
+        assert_eq!(addrs, [IpAddr::from(DUAL_STACK_V6)]);
+        assert_eq!(queries.try_iter().collect::<Vec<_>>(), [RecordType::AAAA]);
+    }
+
+    #[test]
+    fn test_resolve_socket_addrs_carries_port() {
+        let resolver = Resolver::new();
+
+        let addrs = resolver.resolve_socket_addrs("192.0.2.1", 8080).unwrap();
+
+        assert_eq!(addrs, vec!["192.0.2.1:8080".parse().unwrap()]);
+        assert!(addrs.iter().all(|addr| addr.port() == 8080));
+    }
+
+    #[test]
+    fn test_serve_stale_when_upstream_is_unreachable() {
+        // 192.0.2.0/24 is reserved for documentation, so nothing will ever answer from there
+        let resolver = Resolver::new()
+            .root_server(Ipv4Addr::new(192, 0, 2, 1))
+            .timeout(Duration::from_millis(200))
+            .serve_stale(true);
+        let record = DNSRecord::new(
+            b"example.com".to_vec(),
+            RecordType::A,
+            1,
+            1,
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 80)),
+        )
+        .unwrap();
+        resolver.cache.lock().unwrap().insert(
+            "example.com",
+            RecordType::A,
+            vec![record],
+            Duration::from_millis(1),
+        );
+        std::thread::sleep(Duration::from_millis(10));
+
+        let lookup = resolver.lookup("example.com", RecordType::A).unwrap();
+
+        assert!(lookup.is_stale());
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 80))
+        );
+    }
+
+    #[test]
+    fn test_resolve_aborts_at_deadline() {
+        // 192.0.2.0/24 is reserved for documentation, so nothing will ever answer from there
+        let resolver = Resolver::new().root_server(Ipv4Addr::new(192, 0, 2, 1));
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(300);
+
+        let result = resolver.resolve_iteratively("www.twitter.com", RecordType::A, deadline);
+
+        assert!(result.is_err());
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(start.elapsed() < Duration::from_millis(600));
+    }
+
+    #[test]
+    fn test_exists_for_existing_name() {
+        let (port, _) = mock_server(0, vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))]);
+
+        assert!(mock_resolver(port).exists("example.com").unwrap());
+    }
+
+    #[test]
+    fn test_exists_for_nonexistent_name() {
+        let (port, _) = mock_server(constants::RCODE_NAME_ERROR as u8, vec![]);
+
+        assert!(!mock_resolver(port)
+            .exists("nonexistent.example.com")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_exists_propagates_timeout() {
+        let resolver = Resolver::new()
+            .root_server(Ipv4Addr::new(192, 0, 2, 1))
+            .timeout(Duration::from_millis(200));
+
+        assert!(resolver.exists("example.com").is_err());
+    }
+
+    #[test]
+    fn test_socket_is_reused_across_lookups() {
+        let (port, queries) = mock_server(0, vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))]);
+        let resolver = mock_resolver(port);
+
+        resolver.lookup("a.example.com", RecordType::A).unwrap();
+        resolver.lookup("b.example.com", RecordType::A).unwrap();
+
+        let first = queries.recv().unwrap();
+        let second = queries.recv().unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_lookup_reports_elapsed_time() {
+        let (port, _) = mock_server(0, vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))]);
+        let resolver = mock_resolver(port);
+
+        let start = Instant::now();
+        let lookup = resolver.lookup("example.com", RecordType::A).unwrap();
+        let bracket = start.elapsed();
+
+        assert!(lookup.elapsed() > Duration::ZERO);
+        assert!(lookup.elapsed() <= bracket);
+    }
+
+    #[test]
+    fn test_randomized_query_name_has_mixed_case() {
+        let name = "abcdefghijklmnopqrstuvwxyz.example.com";
+
+        let randomized = randomize_case(name);
+
+        assert!(randomized.eq_ignore_ascii_case(name));
+        assert!(randomized.chars().any(|c| c.is_ascii_uppercase()));
+        assert!(randomized.chars().any(|c| c.is_ascii_lowercase()));
+    }
+
+    #[test]
+    fn test_response_with_mismatched_case_is_rejected() {
+        let mut data = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 0];
+        data.extend(encode_dns_name("example.com").unwrap());
+        data.extend([0, 1, 0, 1]);
+        let packet = DNSPacket::try_from(&data[..]).unwrap();
+
+        assert!(verify_question_case(&packet, "example.com").is_ok());
+        assert!(verify_question_case(&packet, "eXaMpLe.CoM").is_err());
+    }
+
+    #[test]
+    fn test_lookup_with_case_randomization() {
+        let (port, _) = mock_server(0, vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))]);
+        let resolver = mock_resolver(port).case_randomization(true);
+
+        assert!(resolver.lookup("example.com", RecordType::A).is_ok());
+    }
+
+    fn cookie_option(query: &[u8]) -> Vec<u8> {
+        let packet = DNSPacket::try_from(query).unwrap();
+        edns::find_option(&packet, constants::EDNS_OPTION_COOKIE)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_query_carries_client_cookie() {
+        let resolver = Resolver::new().edns_cookies(true);
+        let server = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 53);
+
+        let cookie = cookie_option(
+            &resolver
+                .build_query(server, "example.com", RecordType::A, Class::In)
+                .unwrap(),
+        );
+
+        assert_eq!(cookie.len(), constants::CLIENT_COOKIE_SIZE);
+        assert_eq!(cookie, resolver.client_cookie);
+    }
+
+    #[test]
+    fn test_server_cookie_is_sent_back() {
+        let resolver = Resolver::new().edns_cookies(true);
+        let server = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 53);
+        // A response is close enough to the query for this, the OPT record is all that matters
+        let server_cookie = [0xab; 16];
+        let response = QueryBuilder::new("example.com", RecordType::A)
+            .edns_option(
+                constants::EDNS_OPTION_COOKIE,
+                [&resolver.client_cookie[..], &server_cookie].concat(),
+            )
+            .build()
+            .unwrap();
+        let response = DNSPacket::try_from(&response[..]).unwrap();
+
+        resolver.remember_server_cookie(server, &response).unwrap();
+        let cookie = cookie_option(
+            &resolver
+                .build_query(server, "example.com", RecordType::A, Class::In)
+                .unwrap(),
+        );
+
+        assert_eq!(
+            cookie.len(),
+            constants::CLIENT_COOKIE_SIZE + server_cookie.len()
+        );
+        assert_eq!(&cookie[constants::CLIENT_COOKIE_SIZE..], server_cookie);
+    }
+
+    #[test]
+    fn test_response_with_wrong_client_cookie_is_rejected() {
+        let resolver = Resolver::new().edns_cookies(true);
+        let server = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 53);
+        let response = QueryBuilder::new("example.com", RecordType::A)
+            .edns_option(constants::EDNS_OPTION_COOKIE, vec![0; 16])
+            .build()
+            .unwrap();
+        let response = DNSPacket::try_from(&response[..]).unwrap();
+
+        assert!(resolver.remember_server_cookie(server, &response).is_err());
+    }
+
+    #[test]
+    fn test_nameservers_use_glue() {
+        let nameservers = [
+            ("ns1.example.com", Ipv4Addr::new(192, 0, 2, 53)),
+            ("ns2.example.com", Ipv4Addr::new(198, 51, 100, 53)),
+        ];
+        let answers = nameservers
+            .iter()
+            .map(|(name, _)| {
+                let name = encode_dns_name(name).unwrap();
+                let mut bytes = vec![0xc0, 12, 0, 2, 0, 1, 0, 0, 0x0e, 0x10];
+                bytes.extend((name.len() as u16).to_be_bytes());
+                bytes.extend(name);
+                bytes
+            })
+            .collect();
+        let additionals = nameservers
+            .iter()
+            .map(|(name, ip)| {
+                let mut bytes = encode_dns_name(name).unwrap();
+                bytes.extend([0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4]);
+                bytes.extend(ip.octets());
+                bytes
+            })
+            .collect();
+        let (port, _) = mock_server_with_additionals(0, answers, additionals);
+
+        let found = mock_resolver(port).nameservers("example.com").unwrap();
+
+        assert_eq!(
+            found,
+            nameservers
+                .iter()
+                .map(|(name, ip)| (name.to_string(), vec![IpAddr::V4(*ip)]))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_lookup_types_groups_by_type() {
+        let exchange = encode_dns_name("mail.example.com").unwrap();
+        let mut mx_record = vec![0xc0, 12, 0, 15, 0, 1, 0, 0, 0x0e, 0x10];
+        mx_record.extend((exchange.len() as u16 + 2).to_be_bytes());
+        mx_record.extend(10u16.to_be_bytes());
+        mx_record.extend(exchange);
+        let (port, _) = mock_server(
+            0,
+            vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80)), mx_record],
+        );
+
+        let records = mock_resolver(port)
+            .lookup_types("example.com", &[RecordType::A, RecordType::MX])
+            .unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[&RecordType::A][0].get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 80))
+        );
+        assert_eq!(records[&RecordType::MX].len(), 1);
+    }
+
+    #[test]
+    fn test_minimal_responses_keep_only_answers() {
+        let (port, _) = mock_server_with_additionals(
+            0,
+            vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))],
+            vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 53))],
+        );
+
+        let full = mock_resolver(port)
+            .lookup_packet("example.com", RecordType::A)
+            .unwrap();
+        let minimal = mock_resolver(port)
+            .minimal_responses(true)
+            .lookup_packet("example.com", RecordType::A)
+            .unwrap();
+
+        assert_eq!(full.additionals().len(), 1);
+        assert_eq!(minimal.answers().len(), 1);
+        assert!(minimal.authorities().is_empty());
+        assert!(minimal.additionals().is_empty());
+    }
+
+    #[test]
+    fn test_zero_ttl_answer_is_not_cached() {
+        let mut answer = a_record_bytes(Ipv4Addr::new(192, 0, 2, 80));
+        answer[6..10].copy_from_slice(&0u32.to_be_bytes());
+        let (port, _) = mock_server(0, vec![answer]);
+        let resolver = mock_resolver(port);
+
+        let lookup = resolver.lookup("example.com", RecordType::A).unwrap();
+
+        assert_eq!(lookup.records().len(), 1);
+        assert!(resolver
+            .cache
+            .lock()
+            .unwrap()
+            .get("example.com", RecordType::A)
+            .is_none());
+    }
+
+    #[test]
+    fn test_recursion_not_available() {
+        // The mock echoes RD back but never sets RA
+        let (port, _) = mock_server(0, vec![]);
+
+        let error = mock_resolver(port)
+            .recursion_desired(true)
+            .lookup("example.com", RecordType::A)
+            .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<DnsError>(),
+            Some(DnsError::RecursionNotAvailable(_))
+        ));
+    }
+
+    #[test]
+    fn test_recursive_resolver_answers_in_one_query() {
+        let cloudflare = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        assert_eq!(Resolver::recursive(cloudflare).root_server, cloudflare);
+
+        let (port, queries) = mock_server(0, vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))]);
+        let resolver = Resolver::recursive(Ipv4Addr::LOCALHOST.into())
+            .port(port)
+            .timeout(Duration::from_millis(500));
+
+        let lookup = resolver.lookup("www.example.com", RecordType::A).unwrap();
+
+        assert_eq!(lookup.records().len(), 1);
+        assert_eq!(queries.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_trace_follows_referral() {
+        // The root refers us to example.com's ns.example.com at 127.0.0.1, which then answers.
+        // The question's "example.com" starts at offset 16.
+        let ns_name = encode_dns_name("ns.example.com").unwrap();
+        let mut referral = vec![0xc0, 16, 0, 2, 0, 1, 0, 0, 0x0e, 0x10];
+        referral.extend((ns_name.len() as u16).to_be_bytes());
+        referral.extend(&ns_name);
+        let mut glue = ns_name;
+        glue.extend([0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 127, 0, 0, 1]);
+        let (port, _) = scripted_server(vec![
+            MockResponse {
+                authorities: vec![referral],
+                additionals: vec![glue],
+                ..Default::default()
+            },
+            MockResponse {
+                answers: vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))],
+                ..Default::default()
+            },
+        ]);
+
+        let lookup = mock_resolver(port)
+            .trace(true)
+            .lookup("www.example.com", RecordType::A)
+            .unwrap();
+
+        let outcomes: Vec<_> = lookup.trace().iter().map(|step| &step.outcome).collect();
+        assert_eq!(outcomes, [&TraceOutcome::Referral, &TraceOutcome::Answer]);
+        assert!(lookup
+            .trace()
+            .iter()
+            .all(|step| step.domain_name == "www.example.com"));
+    }
+
+    #[test]
+    fn test_lenient_accepts_answer_in_authority() {
+        let (port, _) = scripted_server(vec![MockResponse {
+            authorities: vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))],
+            ..Default::default()
+        }]);
+
+        let lookup = mock_resolver(port)
+            .lenient(true)
+            .lookup("example.com", RecordType::A)
+            .unwrap();
+
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 80))
+        );
+        let strict = mock_resolver(port).lookup("example.com", RecordType::A);
+        assert!(matches!(
+            strict.unwrap_err().downcast_ref::<DnsError>(),
+            Some(DnsError::NoData(_))
+        ));
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let resolver = Resolver::new().retry_backoff(Duration::from_millis(100));
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let backoffs: Vec<_> = (0..3).map(|n| resolver.backoff(n, &mut rng)).collect();
+
+        assert_eq!(
+            backoffs,
+            [100, 200, 400].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_jittered_backoff_stays_within_half_either_way() {
+        let resolver = Resolver::new()
+            .retry_backoff(Duration::from_millis(100))
+            .retry_jitter(true);
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for attempt in 0..8 {
+            let backoff = resolver.backoff(attempt, &mut rng);
+            let base = Duration::from_millis(100) * 2u32.pow(attempt);
+            assert!(backoff >= base / 2 && backoff <= base * 3 / 2);
+        }
+    }
+
+    #[test]
+    fn test_retry_after_timeout() {
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = server.local_addr().unwrap().port();
+        // Drop the first query on the floor, answer the second
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            server.recv_from(&mut buffer).unwrap();
+            let (length, peer) = server.recv_from(&mut buffer).unwrap();
+            let mut response = buffer[..length].to_vec();
+            response[2] |= 0b1000_0100;
+            response[7] = 1;
+            response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 80)));
+            server.send_to(&response, peer).unwrap();
+        });
+
+        let lookup = mock_resolver(port)
+            .retries(1)
+            .retry_backoff(Duration::from_millis(10))
+            .lookup("example.com", RecordType::A)
+            .unwrap();
+
+        assert_eq!(lookup.records().len(), 1);
+    }
+
+    #[test]
+    fn test_lookup_reports_both_forms_of_an_idn() {
+        let (port, _) = mock_server(0, vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))]);
+
+        let lookup = mock_resolver(port)
+            .lookup("bücher.example", RecordType::A)
+            .unwrap();
+
+        assert_eq!(lookup.ascii_name(), "xn--bcher-kva.example");
+        assert_eq!(lookup.unicode_name(), "bücher.example");
+        assert_eq!(lookup.records().len(), 1);
+    }
+
+    #[test]
+    fn test_cname_chain_longer_than_max_hops() {
+        let script = (1..=5)
+            .map(|hop| MockResponse {
+                answers: vec![cname_record_bytes(&format!("hop{}.example.com", hop))],
+                ..Default::default()
+            })
+            .collect();
+        let (port, _) = scripted_server(script);
+        let resolver = mock_resolver(port).max_cname_hops(3);
+
+        let error = resolver.lookup("example.com", RecordType::A).unwrap_err();
+
+        let Some(DnsError::CnameLoop { chain }) = error.downcast_ref::<DnsError>() else {
+            panic!("Expected a CNAME loop, got {}", error);
+        };
+        assert_eq!(
+            chain,
+            &[
+                "example.com",
+                "hop1.example.com",
+                "hop2.example.com",
+                "hop3.example.com",
+                "hop4.example.com",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lookup_first_address_does_not_wait_for_slow_family() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        // Answers A queries and never AAAA ones
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                let query = DNSPacket::parse(&buffer[..length]).unwrap();
+                if query.questions()[0].type_() != RecordType::A {
+                    continue;
+                }
+                let mut response = buffer[..length].to_vec();
+                response[2] |= 0b1000_0100;
+                response[7] = 1;
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 1)));
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        let resolver = Arc::new(
+            mock_resolver(port)
+                .address_family_preference(AddressFamilyPreference::V6First)
+                .timeout(Duration::from_secs(2)),
+        );
+
+        let start = Instant::now();
+        let lookup = resolver.lookup_first_address("example.com").unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+
+    #[test]
+    fn test_cache_can_be_inspected_and_cleared() {
+        let (port, _) = mock_server(0, vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 1))]);
+        let resolver = mock_resolver(port);
+        resolver.lookup("example.com", RecordType::A).unwrap();
+
+        assert_eq!(resolver.cache_len(), 1);
+        let entry = resolver.cache_entry("example.com", RecordType::A).unwrap();
+        assert_eq!(
+            entry.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 1))
+        );
+
+        resolver.cache_clear();
+
+        assert_eq!(resolver.cache_len(), 0);
+        assert!(resolver.cache_entry("example.com", RecordType::A).is_none());
+    }
+
+    #[test]
+    fn test_empty_non_terminal_is_nodata() {
+        // ent.example.com has no records of its own, only names below it do. The server says so
+        // with NOERROR, no answers and the zone's SOA.
+        let (port, _) = scripted_server(vec![MockResponse {
+            authorities: vec![soa_record_bytes("example.com")],
+            ..Default::default()
+        }]);
+
+        let error = mock_resolver(port)
+            .lookup("ent.example.com", RecordType::A)
+            .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<DnsError>(),
+            Some(DnsError::NoData(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_with_custom_selector() {
+        let answers = [60u32, 3600, 300]
+            .into_iter()
+            .enumerate()
+            .map(|(index, ttl)| {
+                let mut record = a_record_bytes(Ipv4Addr::new(192, 0, 2, index as u8));
+                record[6..10].copy_from_slice(&ttl.to_be_bytes());
+                record
+            })
+            .collect();
+        let (port, _) = mock_server(0, answers);
+        let deadline = Instant::now() + Duration::from_millis(500);
+
+        let answer = mock_resolver(port)
+            .resolve_with("example.com", RecordType::A, deadline, |answers| {
+                answers.iter().max_by_key(|record| record.ttl())
+            })
+            .unwrap();
+
+        assert_eq!(answer.ttl(), 3600);
+        assert_eq!(answer.data().get_A(), Some(&Ipv4Addr::new(192, 0, 2, 1)));
+    }
+
+    #[test]
+    fn test_queries_past_the_rate_limit_are_delayed() {
+        let (port, _) = mock_server(0, vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 1))]);
+        let resolver = mock_resolver(port).queries_per_second(5);
+
+        let start = Instant::now();
+        // Twice the burst, so the second half has to wait for tokens at 200ms each
+        for _ in 0..10 {
+            resolver
+                .lookup_packet("example.com", RecordType::A)
+                .unwrap();
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(800));
+    }
+
+    #[test]
+    fn test_rebinding_protection_rejects_loopback_answer() {
+        let (port, _) = mock_server(0, vec![a_record_bytes(Ipv4Addr::LOCALHOST)]);
+        let resolver = mock_resolver(port).address_filter(AddressFilter::rebinding_protection());
+
+        let error = resolver
+            .lookup("rebind.example.com", RecordType::A)
+            .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<DnsError>(),
+            Some(DnsError::DeniedAddress { address, .. }) if *address == Ipv4Addr::LOCALHOST
+        ));
+        assert_eq!(resolver.cache_len(), 0);
+    }
+
+    #[test]
+    fn test_resolve_expect_matching_address() {
+        let (port, _) = mock_server(
+            0,
+            vec![
+                a_record_bytes(Ipv4Addr::new(192, 0, 2, 1)),
+                a_record_bytes(Ipv4Addr::new(192, 0, 2, 2)),
+            ],
+        );
+
+        assert!(mock_resolver(port)
+            .resolve_expect("example.com", Ipv4Addr::new(192, 0, 2, 2))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_resolve_expect_other_address() {
+        let (port, _) = mock_server(0, vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 1))]);
+
+        assert!(!mock_resolver(port)
+            .resolve_expect("example.com", Ipv4Addr::new(198, 51, 100, 1))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_identical_lookups_share_one_query() {
+        let (port, queries) = scripted_server(vec![MockResponse {
+            delay: Duration::from_millis(200),
+            answers: vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 1))],
+            ..Default::default()
+        }]);
+        let resolver = mock_resolver(port);
+
+        thread::scope(|scope| {
+            let lookups: Vec<_> = (0..20)
+                .map(|_| scope.spawn(|| resolver.lookup("example.com", RecordType::A).unwrap()))
+                .collect();
+            let mut resolving = 0;
+            for lookup in lookups {
+                let lookup = lookup.join().unwrap();
+                assert_eq!(
+                    lookup.records()[0].data().get_A(),
+                    Some(&Ipv4Addr::new(192, 0, 2, 1))
+                );
+                // Lookups that waited for another one to resolve the name can't tell how it went
+                let waited = !lookup.is_cache_hit() && lookup.transport().is_none();
+                assert_eq!(lookup.referrals().is_none(), waited);
+                resolving += lookup.transport().is_some() as usize;
+            }
+            assert_eq!(resolving, 1);
+        });
+
+        assert_eq!(queries.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_lookup_over_tcp_only() {
+        // Nothing listens on UDP, so only a query over TCP can get an answer
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut length = [0; 2];
+            while stream.read_exact(&mut length).is_ok() {
+                let mut response = vec![0; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut response).unwrap();
+                response[2] |= 0b1000_0100;
+                response[7] = 1;
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 1)));
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+        let resolver = mock_resolver(port).transport(Transport::Tcp);
+
+        let lookup = resolver.lookup("example.com", RecordType::A).unwrap();
+
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+
+    #[test]
+    fn test_pooled_connection_closed_by_the_server_is_replaced() {
+        // Every connection carries one query, after which the server closes it
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, connections) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let _ = sender.send(());
+                let mut length = [0; 2];
+                stream.read_exact(&mut length).unwrap();
+                let mut response = vec![0; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut response).unwrap();
+                response[2] |= 0b1000_0100;
+                response[7] = 1;
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 1)));
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+        // No retries, so only the pool itself can recover from the closed connection
+        let resolver = mock_resolver(port).transport(Transport::Tcp).retries(0);
+
+        resolver.lookup("a.example.com", RecordType::A).unwrap();
+        let lookup = resolver.lookup("b.example.com", RecordType::A).unwrap();
+
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 1))
+        );
+        assert_eq!(connections.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_zone_of_walks_up_to_the_apex() {
+        let (port, queries) = scripted_server(vec![
+            MockResponse::default(),
+            MockResponse::default(),
+            MockResponse {
+                answers: vec![soa_record_bytes("example.com")],
+                ..Default::default()
+            },
+        ]);
+
+        let (zone, soa) = mock_resolver(port).zone_of("www.sub.example.com").unwrap();
+
+        assert_eq!(zone, "example.com");
+        assert_eq!(soa.mname, "ns1.example.com");
+        assert_eq!(soa.serial, 1);
+        assert_eq!(queries.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn test_datagram_shorter_than_a_header() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            let (_, peer) = socket.recv_from(&mut buffer).unwrap();
+            // The query's ID and flags, then nothing but half the counts
+            socket.send_to(&buffer[..8], peer).unwrap();
+        });
+
+        let error = mock_resolver(port)
+            .lookup("example.com", RecordType::A)
+            .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<DnsError>(),
+            Some(DnsError::ShortResponse(8))
+        ));
+    }
+
+    #[test]
+    fn test_lookup_with_chaos_class() {
+        // Only answers queries in the CH class, and refuses the rest
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                let query = DNSPacket::parse(&buffer[..length]).unwrap();
+                let mut response = buffer[..length].to_vec();
+                response[2] |= 0b1000_0100;
+                if query.questions()[0].class() == Class::Ch {
+                    response[7] = 1;
+                    response.extend(a_record_bytes(Ipv4Addr::new(10, 0, 0, 1)));
+                } else {
+                    response[3] = 5; // REFUSED
+                }
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        let resolver = mock_resolver(port);
+
+        let lookup = resolver
+            .lookup_with_class("version.bind", RecordType::A, Class::Ch)
+            .unwrap();
+
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert!(resolver.lookup("version.bind", RecordType::A).is_err());
+        assert_eq!(resolver.cache_len(), 0);
+    }
+
+    #[test]
+    fn test_lookup_cname() {
+        let (port, queries) = mock_server(0, vec![cname_record_bytes("target.example.com")]);
+        let resolver = mock_resolver(port);
+
+        let target = resolver.lookup_cname("www.example.com").unwrap();
+
+        assert_eq!(target.as_deref(), Some("target.example.com"));
+        // The target itself is never asked for
+        assert_eq!(queries.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_lookup_cname_of_a_name_without_one() {
+        let (port, _) = mock_server(0, vec![]);
+        let resolver = mock_resolver(port);
+
+        assert_eq!(resolver.lookup_cname("example.com").unwrap(), None);
+    }
+
+    #[test]
+    fn test_nameserver_without_glue_is_looked_up_once() {
+        // Both www.example.com and the alias.example.com it points at are referred to
+        // ns.other.net without glue, whose address is only asked for the first time
+        let referral = |zone_offset| {
+            let ns_name = encode_dns_name("ns.other.net").unwrap();
+            let mut bytes = vec![0xc0, zone_offset, 0, 2, 0, 1, 0, 0, 0x0e, 0x10];
+            bytes.extend((ns_name.len() as u16).to_be_bytes());
+            bytes.extend(ns_name);
+            bytes
+        };
+        let (port, queries) = scripted_server(vec![
+            MockResponse {
+                authorities: vec![referral(16)],
+                ..Default::default()
+            },
+            MockResponse {
+                answers: vec![a_record_bytes(Ipv4Addr::LOCALHOST)],
+                ..Default::default()
+            },
+            MockResponse {
+                answers: vec![cname_record_bytes("alias.example.com")],
+                ..Default::default()
+            },
+            MockResponse {
+                authorities: vec![referral(18)],
+                ..Default::default()
+            },
+            MockResponse {
+                answers: vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))],
+                ..Default::default()
+            },
+        ]);
+
+        let lookup = mock_resolver(port)
+            .lookup("www.example.com", RecordType::A)
+            .unwrap();
+
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 80))
+        );
+        assert_eq!(queries.try_iter().count(), 5);
+    }
+
+    #[test]
+    fn test_search_domains_make_unqualified_name_resolvable() {
+        // Only myhost.corp.example.com exists, every other name is NXDOMAIN
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                let query = DNSPacket::parse(&buffer[..length]).unwrap();
+                let mut response = buffer[..length].to_vec();
+                response[2] |= 0b1000_0100;
+                if query.questions()[0].name() == b"myhost.corp.example.com" {
+                    response[7] = 1;
+                    response.extend(a_record_bytes(Ipv4Addr::new(10, 0, 0, 1)));
+                } else {
+                    response[3] = constants::RCODE_NAME_ERROR as u8;
+                }
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        let resolver = mock_resolver(port).search_domains(vec![
+            "lab.example.com".to_string(),
+            "corp.example.com".to_string(),
+        ]);
+
+        let lookup = resolver.lookup("myhost", RecordType::A).unwrap();
+
+        assert_eq!(lookup.ascii_name(), "myhost.corp.example.com");
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(10, 0, 0, 1))
+        );
+        let error = resolver.lookup("otherhost", RecordType::A).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<DnsError>(),
+            Some(DnsError::NxDomain(_))
+        ));
+    }
+
+    #[test]
+    fn test_ndots_decides_whether_the_name_is_tried_first() {
+        let resolver = Resolver::new()
+            .search_domains(vec!["example.com".to_string()])
+            .ndots(2);
+
+        assert_eq!(
+            resolver.search_candidates("a.b.c"),
+            ["a.b.c", "a.b.c.example.com"]
+        );
+        assert_eq!(
+            resolver.search_candidates("a.b"),
+            ["a.b.example.com", "a.b"]
+        );
+        assert_eq!(resolver.search_candidates("a.b."), ["a.b"]);
+    }
+
+    #[test]
+    fn test_edns_padding_pads_queries_to_the_block_size() {
+        let server = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 53);
+        let resolver = Resolver::new().edns_padding(Some(128));
+
+        let query = resolver
+            .build_query(server, "example.com", RecordType::A, Class::In)
+            .unwrap();
+
+        assert_eq!(query.len(), 128);
+        let with_cookies = resolver
+            .edns_cookies(true)
+            .build_query(
+                server,
+                "a.much.longer.name.than.before.example.com",
+                RecordType::A,
+                Class::In,
+            )
+            .unwrap();
+        assert_eq!(with_cookies.len(), 128);
+    }
+
+    #[test]
+    fn test_resolve_with_timeout_overrides_the_default() {
+        // Receives queries but never answers them
+        let blackhole = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let resolver = Resolver::new()
+            .root_server(Ipv4Addr::LOCALHOST)
+            .port(blackhole.local_addr().unwrap().port())
+            .timeout(Duration::from_secs(5));
+
+        let start = Instant::now();
+        let result =
+            resolver.resolve_with_timeout("example.com", RecordType::A, Duration::from_millis(200));
+
+        assert!(result.is_err());
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200) && elapsed < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_response_without_opt_to_edns_query_is_accepted() {
+        // An older server that drops the OPT record from its response altogether
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                assert_eq!(buffer[11], 1, "the query should carry an OPT record");
+                let question_end = 12 + encode_dns_name("example.com").unwrap().len() + 4;
+                let mut response = buffer[..question_end.min(length)].to_vec();
+                response[2] |= 0b1000_0100;
+                response[7] = 1;
+                response[11] = 0;
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 80)));
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+
+        let lookup = mock_resolver(port)
+            .edns_cookies(true)
+            .lookup("example.com", RecordType::A)
+            .unwrap();
+
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 80))
+        );
+    }
+
+    #[test]
+    fn test_cancelling_mid_resolution_returns_promptly() {
+        // Receives queries but never answers them
+        let blackhole = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let resolver = Resolver::new()
+            .root_server(Ipv4Addr::LOCALHOST)
+            .port(blackhole.local_addr().unwrap().port())
+            .timeout(Duration::from_secs(5));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let canceller = Arc::clone(&cancel);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            canceller.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+
+        let start = Instant::now();
+        let error = resolver
+            .resolve_cancellable("example.com", RecordType::A, &cancel)
+            .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<DnsError>(),
+            Some(DnsError::Cancelled)
+        ));
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_invalid_name_fails_without_querying() {
+        let (port, queries) = mock_server(0, vec![a_record_bytes(Ipv4Addr::LOCALHOST)]);
+
+        let resolver = mock_resolver(port).strict_names(true);
+
+        let result = resolver.lookup("exa mple.com", RecordType::A);
+
+        assert!(result.is_err());
+        assert_eq!(queries.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_names_that_are_not_hostnames_are_looked_up() {
+        let (port, queries) = mock_server(0, vec![a_record_bytes(Ipv4Addr::LOCALHOST)]);
+        let resolver = mock_resolver(port);
+
+        for name in [
+            "*.example.com",
+            "0/25.2.0.192.in-addr.arpa",
+            "a\\.b.example.com",
+        ] {
+            assert!(resolver.lookup(name, RecordType::A).is_ok(), "{:?}", name);
+        }
+        assert_eq!(queries.try_iter().count(), 3);
+
+        let too_long = format!("{}.example.com", "a".repeat(64));
+        assert!(resolver.lookup(&too_long, RecordType::A).is_err());
+        assert_eq!(queries.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_owner_name_is_the_cname_target() {
+        let (port, _) = scripted_server(vec![
+            MockResponse {
+                answers: vec![cname_record_bytes("example.com")],
+                ..Default::default()
+            },
+            MockResponse {
+                answers: vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))],
+                ..Default::default()
+            },
+        ]);
+
+        let lookup = mock_resolver(port)
+            .lookup("www.example.com", RecordType::A)
+            .unwrap();
+
+        assert_eq!(lookup.ascii_name(), "www.example.com");
+        assert_eq!(lookup.owner_name(), Some("example.com"));
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 80))
+        );
+    }
+
+    #[test]
+    fn test_owner_name_skips_the_cname_in_the_same_answer() {
+        // The A record's owner points at "example.com" within the question's "www.example.com"
+        let mut a_record = vec![0xc0, 16, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4];
+        a_record.extend([192, 0, 2, 80]);
+        let (port, _) = mock_server(0, vec![cname_record_bytes("example.com"), a_record]);
+
+        let lookup = mock_resolver(port)
+            .lookup("www.example.com", RecordType::A)
+            .unwrap();
+
+        assert_eq!(lookup.records().len(), 2);
+        assert_eq!(lookup.records()[0].type_(), RecordType::CNAME);
+        assert_eq!(lookup.owner_name(), Some("example.com"));
+    }
+
+    #[cfg(feature = "socks5")]
+    #[test]
+    fn test_lookup_through_socks5_proxy() {
+        // A proxy that serves the DNS server it was asked to connect to itself, which is at an
+        // address we could never reach directly
+        let server = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 53).into(), 53);
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let proxy = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[5, 0]).unwrap();
+            let mut request = [0; 10];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request[..4], [5, 1, 0, 1]);
+            assert_eq!(request[4..], [192, 0, 2, 53, 0, 53]);
+            stream.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap();
+
+            let mut length = [0; 2];
+            while stream.read_exact(&mut length).is_ok() {
+                let mut response = vec![0; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut response).unwrap();
+                response[2] |= 0b1000_0100;
+                response[7] = 1;
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 1)));
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+        let resolver = Resolver::new()
+            .root_server(server.ip())
+            .port(server.port())
+            .timeout(Duration::from_millis(500))
+            .socks5_proxy(proxy);
+
+        let lookup = resolver.lookup("example.com", RecordType::A).unwrap();
+
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+
+    #[test]
+    fn test_response_with_duplicate_question_is_rejected_unless_lenient() {
+        // Repeats the question it was asked, claiming 2 questions
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                let mut response = buffer[..length].to_vec();
+                response[2] |= 0b1000_0100;
+                response[5] = 2;
+                response[7] = 1;
+                response.extend_from_slice(&buffer[12..length]);
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 80)));
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+
+        let strict = mock_resolver(port).lookup("example.com", RecordType::A);
+        let lenient = mock_resolver(port)
+            .lenient(true)
+            .lookup("example.com", RecordType::A);
+
+        assert_eq!(
+            strict.unwrap_err().to_string(),
+            "Response has 2 questions to our query's 1"
+        );
+        assert_eq!(
+            lenient.unwrap().records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 80))
+        );
+    }
+
+    #[test]
+    fn test_fixed_query_id_makes_queries_reproducible() {
+        let server = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 53);
+        let resolver = Resolver::new().query_id(0);
+
+        let first = resolver
+            .build_query(server, "example.com", RecordType::A, Class::In)
+            .unwrap();
+        let second = resolver
+            .build_query(server, "example.com", RecordType::A, Class::In)
+            .unwrap();
+
+        assert_eq!(first[0..2], [0, 0]);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_max_inflight_caps_concurrent_queries() {
+        // Answers every query 50ms late, keeping track of how many are waiting at once
+        let socket = Arc::new(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap());
+        let port = socket.local_addr().unwrap().port();
+        let waiting = Arc::new(Mutex::new((0, 0)));
+        let (server_socket, server_waiting) = (Arc::clone(&socket), Arc::clone(&waiting));
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            while let Ok((length, peer)) = server_socket.recv_from(&mut buffer) {
+                let mut response = buffer[..length].to_vec();
+                response[2] |= 0b1000_0100;
+                response[7] = 1;
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 80)));
+                {
+                    let (now, most) = &mut *server_waiting.lock().unwrap();
+                    *now += 1;
+                    *most = (*most).max(*now);
+                }
+                let (socket, waiting) = (Arc::clone(&server_socket), Arc::clone(&server_waiting));
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(50));
+                    waiting.lock().unwrap().0 -= 1;
+                    socket.send_to(&response, peer).unwrap();
+                });
+            }
+        });
+        let resolver = mock_resolver(port)
+            .timeout(Duration::from_secs(2))
+            .max_inflight(2);
+
+        thread::scope(|scope| {
+            for n in 0..10 {
+                let resolver = &resolver;
+                scope.spawn(move || {
+                    resolver
+                        .lookup(&format!("host{}.example.com", n), RecordType::A)
+                        .unwrap();
+                });
+            }
+        });
+
+        assert_eq!(waiting.lock().unwrap().1, 2);
+    }
+
+    #[test]
+    fn test_waiting_for_a_permit_is_retried() {
+        let (port, queries) = mock_server(0, vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))]);
+        let resolver = mock_resolver(port)
+            .timeout(Duration::from_secs(1))
+            .retries(1)
+            .max_inflight(1);
+        // Held past the first attempt's half of the timeout, and given back during the second
+        let held = resolver
+            .inflight_limit
+            .as_ref()
+            .unwrap()
+            .acquire(Instant::now())
+            .unwrap();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(700));
+                drop(held);
+            });
+
+            let lookup = resolver.lookup("example.com", RecordType::A).unwrap();
+
+            assert_eq!(lookup.records().len(), 1);
+        });
+        assert_eq!(queries.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_resolve_reported() {
+        // Same referral as in test_trace_follows_referral
+        let ns_name = encode_dns_name("ns.example.com").unwrap();
+        let mut referral = vec![0xc0, 16, 0, 2, 0, 1, 0, 0, 0x0e, 0x10];
+        referral.extend((ns_name.len() as u16).to_be_bytes());
+        referral.extend(&ns_name);
+        let mut glue = ns_name;
+        glue.extend([0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 127, 0, 0, 1]);
+        let (port, _) = scripted_server(vec![
+            MockResponse {
+                authorities: vec![referral],
+                additionals: vec![glue],
+                ..Default::default()
+            },
+            MockResponse {
+                answers: vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))],
+                ..Default::default()
+            },
+        ]);
+        let resolver = mock_resolver(port);
+
+        let report = resolver
+            .resolve_reported("www.example.com", RecordType::A)
+            .unwrap();
+        assert_eq!(report.domain_name, "www.example.com");
+        assert_eq!(report.record_type, RecordType::A);
+        assert_eq!(report.addresses, [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 80))]);
+        assert_eq!(report.ttl, 3600);
+        assert!(report.authoritative);
+        assert!(report.elapsed > Duration::ZERO);
+        assert_eq!(report.transport, Some(Transport::Udp));
+        assert_eq!(report.referrals, Some(1));
+        assert!(!report.cache_hit);
+
+        let report = resolver
+            .resolve_reported("www.example.com", RecordType::A)
+            .unwrap();
+        assert!(report.cache_hit);
+        assert_eq!(report.transport, None);
+        assert_eq!(report.referrals, Some(0));
+        assert_eq!(report.addresses.len(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_bind_device_loopback() {
+        let (port, queries) = mock_server(0, vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 1))]);
+        let resolver = mock_resolver(port).bind_device("lo".to_string());
+
+        let lookup = resolver.lookup("example.com", RecordType::A).unwrap();
+        assert_eq!(lookup.records().len(), 1);
+        assert_eq!(queries.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_transport_chain_falls_back_to_tcp() {
+        // UDP queries go unanswered, so only the TCP fallback can get an answer
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let blackhole = UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut length = [0; 2];
+            while stream.read_exact(&mut length).is_ok() {
+                let mut response = vec![0; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut response).unwrap();
+                response[2] |= 0b1000_0100;
+                response[7] = 1;
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 1)));
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+        let resolver = mock_resolver(port).transport_chain(vec![Transport::Udp, Transport::Tcp]);
+
+        let lookup = resolver.lookup("example.com", RecordType::A).unwrap();
+
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 1))
+        );
+        assert_eq!(lookup.transport(), Some(Transport::Tcp));
+        let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+        blackhole.set_nonblocking(true).unwrap();
+        assert!(blackhole.recv_from(&mut buffer).is_ok());
+    }
+
+    #[test]
+    fn test_report_names_the_transport_that_answered_after_truncation() {
+        // UDP answers are truncated, so the full answer comes over TCP
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                let mut response = buffer[..length].to_vec();
+                response[2] |= 0b1000_0110;
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut length = [0; 2];
+            while stream.read_exact(&mut length).is_ok() {
+                let mut response = vec![0; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut response).unwrap();
+                response[2] |= 0b1000_0100;
+                response[7] = 1;
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 1)));
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+        let resolver = mock_resolver(port).transport_chain(vec![Transport::Udp, Transport::Tcp]);
+
+        let report = resolver
+            .resolve_reported("example.com", RecordType::A)
+            .unwrap();
+
+        assert_eq!(report.addresses, [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+        assert_eq!(report.transport, Some(Transport::Tcp));
+    }
+
+    #[cfg(feature = "doh")]
+    #[test]
+    fn test_transport_chain_falls_back_to_doh() {
+        use std::io::{BufRead, BufReader};
+
+        // UDP queries go unanswered and nothing listens for TCP, so only DoH can get an answer
+        let blackhole = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = blackhole.local_addr().unwrap().port();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                let header = line.to_lowercase();
+                if let Some(value) = header.strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                if header.starts_with("content-type:") {
+                    assert_eq!(header.trim(), "content-type: application/dns-message");
+                }
+                line.clear();
+            }
+            let mut response = vec![0; length];
+            reader.read_exact(&mut response).unwrap();
+            response[2] |= 0b1000_0100;
+            response[7] = 1;
+            response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 1)));
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+                response.len()
+            )
+            .unwrap();
+            stream.write_all(&response).unwrap();
+        });
+        let resolver = Resolver::new()
+            .root_server(Ipv4Addr::LOCALHOST)
+            .port(port)
+            .timeout(Duration::from_millis(1500))
+            .transport_chain(vec![Transport::Udp, Transport::Tcp, Transport::Doh])
+            .doh_url(url);
+
+        let lookup = resolver.lookup("example.com", RecordType::A).unwrap();
+
+        assert_eq!(
+            lookup.records()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 1))
+        );
+        assert_eq!(lookup.transport(), Some(Transport::Doh));
+        let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+        blackhole.set_nonblocking(true).unwrap();
+        assert!(blackhole.recv_from(&mut buffer).is_ok());
+    }
+
+    #[test]
+    fn test_lookup_fully_qualified_name() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let (sender, questions) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                let query = DNSPacket::parse(&buffer[..length]).unwrap();
+                let _ = sender.send(query.questions()[0].name().to_vec());
+                let mut response = buffer[..length].to_vec();
+                response[2] |= 0b1000_0100;
+                response[7] = 1;
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 1)));
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        let resolver = mock_resolver(port).search_domains(vec!["corp.example".to_string()]);
+
+        let lookup = resolver.lookup("example.com.", RecordType::A).unwrap();
+
+        assert_eq!(lookup.records().len(), 1);
+        assert_eq!(questions.try_iter().collect::<Vec<_>>(), [b"example.com"]);
+    }
+
+    #[test]
+    fn test_small_max_response_size_advertises_512() {
+        let server = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 53);
+        let resolver = Resolver::new().edns_cookies(true).max_response_size(100);
+
+        let query = resolver
+            .build_query(server, "example.com", RecordType::A, Class::In)
+            .unwrap();
+        let query = DNSPacket::parse(&query).unwrap();
+
+        let opt = &query.additionals()[0];
+        assert_eq!(opt.type_(), RecordType::OPT);
+        assert_eq!(opt.class(), 512);
+    }
+
+    #[test]
+    fn test_glue_is_cached_for_later_referrals() {
+        // Both lookups get referred to ns.example.com, the second time without glue
+        let ns_name = encode_dns_name("ns.example.com").unwrap();
+        let mut referral = vec![0xc0, 16, 0, 2, 0, 1, 0, 0, 0x0e, 0x10];
+        referral.extend((ns_name.len() as u16).to_be_bytes());
+        referral.extend(&ns_name);
+        let mut glue = ns_name;
+        glue.extend([0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 127, 0, 0, 1]);
+        let answer = || MockResponse {
+            answers: vec![a_record_bytes(Ipv4Addr::new(192, 0, 2, 80))],
+            ..Default::default()
+        };
+        let (port, queries) = scripted_server(vec![
+            MockResponse {
+                authorities: vec![referral.clone()],
+                additionals: vec![glue],
+                ..Default::default()
+            },
+            answer(),
+            MockResponse {
+                authorities: vec![referral],
+                ..Default::default()
+            },
+            answer(),
+        ]);
+        let resolver = mock_resolver(port);
+
+        resolver.lookup("www.example.com", RecordType::A).unwrap();
+        // Looking ns.example.com up would take another query
+        resolver.lookup("ftp.example.com", RecordType::A).unwrap();
+        assert_eq!(queries.try_iter().count(), 4);
+
+        // The glue is never handed out as an answer
+        assert!(resolver
+            .cache_entry("ns.example.com", RecordType::A)
+            .is_none());
+        let lookup = resolver.lookup("ns.example.com", RecordType::A).unwrap();
+        assert!(!lookup.is_cache_hit());
+        assert_eq!(
+            lookup.addresses(),
+            [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 80))]
+        );
+        assert_eq!(queries.try_iter().count(), 1);
+    }
+
+    /// Answers every query on the socket with an echo of it that `respond` fills in, given the
+    /// question name, reporting the name of each query
+    fn recording_server(
+        socket: UdpSocket,
+        respond: impl Fn(&str, &mut Vec<u8>) + Send + 'static,
+    ) -> mpsc::Receiver<String> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; constants::UDP_DNS_RESPONSE_SIZE];
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                let query = DNSPacket::parse(&buffer[..length]).unwrap();
+                let name = String::from_utf8(query.questions()[0].name().to_vec()).unwrap();
+                let mut response = buffer[..length].to_vec();
+                response[2] |= 0b1000_0100;
+                respond(&name, &mut response);
+                let _ = sender.send(name);
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        receiver
+    }
+
+    /// A referral to `zone` at `ns_name`, with glue for it at `glue`
+    fn referral_bytes(zone: &str, ns_name: &str, glue: Ipv4Addr) -> Vec<u8> {
+        let ns_name = encode_dns_name(ns_name).unwrap();
+        let mut bytes = encode_dns_name(zone).unwrap();
+        bytes.extend([0, 2, 0, 1, 0, 0, 0x0e, 0x10]);
+        bytes.extend((ns_name.len() as u16).to_be_bytes());
+        bytes.extend(&ns_name);
+        bytes.extend(ns_name);
+        bytes.extend([0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4]);
+        bytes.extend(glue.octets());
+        bytes
+    }
+
+    #[test]
+    fn test_cname_with_referral_queries_the_target_at_the_referred_server() {
+        // The first answer is a CNAME to www.example.net plus a referral to example.net's
+        // nameserver, with glue pointing back at us
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let questions = recording_server(socket, |name, response| {
+            response[7] = 1;
+            if name == "www.example.com" {
+                response[9] = 1;
+                response[11] = 1;
+                response.extend(cname_record_bytes("www.example.net"));
+                response.extend(referral_bytes(
+                    "example.net",
+                    "ns1.example.net",
+                    Ipv4Addr::LOCALHOST,
+                ));
+            } else {
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 1)));
+            }
+        });
+        let resolver = mock_resolver(port);
+
+        let lookup = resolver.lookup("www.example.com", RecordType::A).unwrap();
+
+        assert_eq!(
+            questions.try_iter().collect::<Vec<_>>(),
+            ["www.example.com", "www.example.net"]
+        );
+        assert_eq!(lookup.owner_name(), Some("www.example.net"));
+        assert_eq!(
+            lookup.addresses(),
+            [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]
+        );
+    }
+
+    #[test]
+    fn test_cname_with_its_own_zones_ns_set_restarts_from_the_root() {
+        // The CNAME answer comes with example.com's own NS set, which says nothing about where
+        // www.example.net is. Its nameserver refuses everything.
+        let root = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = root.local_addr().unwrap().port();
+        let example_com_ip = Ipv4Addr::new(127, 0, 0, 2);
+        let example_com = UdpSocket::bind((example_com_ip, port)).unwrap();
+        let root_questions = recording_server(root, move |name, response| {
+            response[7] = 1;
+            if name == "www.example.com" {
+                response[9] = 1;
+                response[11] = 1;
+                response.extend(cname_record_bytes("www.example.net"));
+                response.extend(referral_bytes(
+                    "example.com",
+                    "ns1.example.com",
+                    example_com_ip,
+                ));
+            } else {
+                response.extend(a_record_bytes(Ipv4Addr::new(192, 0, 2, 1)));
+            }
+        });
+        let example_com_questions = recording_server(example_com, |_, response| {
+            // REFUSED
+            response[3] = 5;
+        });
+        let resolver = mock_resolver(port);
+
+        let lookup = resolver.lookup("www.example.com", RecordType::A).unwrap();
+
+        assert_eq!(
+            root_questions.try_iter().collect::<Vec<_>>(),
+            ["www.example.com", "www.example.net"]
+        );
+        assert_eq!(example_com_questions.try_iter().count(), 0);
+        assert_eq!(
+            lookup.addresses(),
+            [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::error::Error;
+
+use crate::{
+    constants, dns_header::DNSHeader, dns_packet::DNSPacket, dns_record::DNSRecord,
+    encode_dns_name_compressed, encode_dns_name_into, NameOffsets,
+};
+
+/// Builds the wire bytes of a response to a parsed query, e.g. for a small authoritative server.
+#[derive(Debug, Clone)]
+pub struct ResponseBuilder<'a> {
+    query: &'a DNSPacket,
+    answers: Vec<DNSRecord>,
+    rcode: u16,
+    compress_names: bool,
+}
+
+impl<'a> ResponseBuilder<'a> {
+    pub fn new(query: &'a DNSPacket, answers: Vec<DNSRecord>) -> Self {
+        Self {
+            query,
+            answers,
+            rcode: constants::RCODE_NO_ERROR,
+            compress_names: false,
+        }
+    }
+
+    /// Answer with an error instead, like RCODE_NAME_ERROR for a name that doesn't exist.
+    pub fn rcode(mut self, rcode: u16) -> Self {
+        self.rcode = rcode;
+        self
+    }
+
+    /// Point repeated names back at their first occurrence, like real servers do.
+    pub fn compress_names(mut self, compress_names: bool) -> Self {
+        self.compress_names = compress_names;
+        self
+    }
+
+    pub fn build(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let query_header = self.query.header();
+        // QR and AA, with the query's opcode and RD carried over
+        let mut flags = 1 << 15
+            | constants::AUTHORITATIVE_ANSWER
+            | query_header.opcode() << 11
+            | (self.rcode & 0b1111);
+        if query_header.flags().rd {
+            flags |= constants::RECURSION_DESIRED;
+        }
+
+        let mut header = DNSHeader::new(query_header.id(), flags);
+        header.set_num_questions(self.query.questions().len() as u16);
+        header.set_num_answers(self.answers.len() as u16);
+
+        let mut bytes = header.to_bytes();
+        let mut offsets = NameOffsets::new();
+        for question in self.query.questions() {
+            let name = String::from_utf8_lossy(question.name());
+            match self.compress_names {
+                true => encode_dns_name_compressed(&name, &mut bytes, &mut offsets)?,
+                false => encode_dns_name_into(&name, &mut bytes)?,
+            }
+            bytes.extend(u16::from(question.type_()).to_be_bytes());
+            bytes.extend((question.class() as u16).to_be_bytes());
+        }
+        for answer in &self.answers {
+            match self.compress_names {
+                true => answer.write_compressed(&mut bytes, &mut offsets)?,
+                false => bytes.extend(answer.to_bytes()?),
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::Ipv4Addr;
+
+    use crate::{query_builder::QueryBuilder, record_data::RecordData, record_type::RecordType};
+
+    use super::*;
+
+    #[test]
+    fn test_response_to_a_query() {
+        let query = QueryBuilder::new("example.com", RecordType::A)
+            .id(0x1234)
+            .build()
+            .unwrap();
+        let query = DNSPacket::try_from(&query[..]).unwrap();
+        let answer = DNSRecord::new(
+            b"example.com".to_vec(),
+            RecordType::A,
+            1,
+            3600,
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 80)),
+        )
+        .unwrap();
+
+        let response = ResponseBuilder::new(&query, vec![answer]).build().unwrap();
+        let response = DNSPacket::try_from(&response[..]).unwrap();
+
+        assert_eq!(response.header().id(), 0x1234);
+        assert!(response.header().flags().qr);
+        assert!(response.header().flags().aa);
+        assert_eq!(response.header().rcode(), constants::RCODE_NO_ERROR);
+        assert_eq!(response.questions()[0].name(), b"example.com");
+        assert_eq!(response.answers()[0].name(), b"example.com");
+        assert_eq!(
+            response.answers()[0].data().get_A(),
+            Some(&Ipv4Addr::new(192, 0, 2, 80))
+        );
+    }
+
+    #[test]
+    fn test_compressed_names_point_at_the_question() {
+        let query = QueryBuilder::new("example.com", RecordType::NS)
+            .build()
+            .unwrap();
+        let query = DNSPacket::try_from(&query[..]).unwrap();
+        let answer = DNSRecord::new(
+            b"example.com".to_vec(),
+            RecordType::NS,
+            1,
+            3600,
+            RecordData::NS("ns.example.com".to_string()),
+        )
+        .unwrap();
+
+        let response = ResponseBuilder::new(&query, vec![answer])
+            .compress_names(true)
+            .build()
+            .unwrap();
+
+        // The question's name starts at offset 12, and takes 13 bytes plus type and class
+        let answer_start = 12 + 13 + 4;
+        assert_eq!(response[answer_start..answer_start + 2], [0xc0, 12]);
+        // RDATA is the "ns" label and a pointer to the question's name
+        let rdata_start = answer_start + 2 + 10;
+        assert_eq!(response[rdata_start - 2..rdata_start], [0, 5]);
+        assert_eq!(response[rdata_start..], [2, b'n', b's', 0xc0, 12]);
+
+        let response = DNSPacket::try_from(&response[..]).unwrap();
+        assert_eq!(response.answers()[0].name(), b"example.com");
+        assert_eq!(
+            response.answers()[0].data().get_NS(),
+            Some("ns.example.com")
+        );
+    }
+}
@@ -0,0 +1,42 @@
+//! DNSSEC signatures as described in https://datatracker.ietf.org/doc/html/rfc4034#section-3
+
+use std::error::Error;
+
+use crate::{cursor::Cursor, parse_options::ParseOptions, record_data::RecordData};
+
+/// Parses RRSIG RDATA of `length` bytes.
+pub(crate) fn parse_rdata(
+    cursor: &mut Cursor,
+    length: usize,
+    options: &ParseOptions,
+) -> Result<RecordData, Box<dyn Error>> {
+    let start = cursor.position();
+
+    Ok(RecordData::RRSIG {
+        type_covered: cursor.read_u16()?.try_into()?,
+        algorithm: cursor.read_u8()?,
+        labels: cursor.read_u8()?,
+        original_ttl: cursor.read_u32()?,
+        expiration: cursor.read_u32()?,
+        inception: cursor.read_u32()?,
+        key_tag: cursor.read_u16()?,
+        signer_name: cursor.read_name(options.name_decoding)?,
+        signature: cursor.read_rest(start, length)?.to_vec(),
+    })
+}
+
+/// Whether a record owned by `owner` and signed with this many `labels` was synthesized from a
+/// wildcard. The labels field counts the labels of the name that was signed, which for a
+/// wildcard is the name without its leading `*`, so it comes out short of the owner's labels.
+/// https://datatracker.ietf.org/doc/html/rfc4035#section-5.3.2
+pub(crate) fn is_wildcard_expansion(owner: &[u8], labels: u8) -> bool {
+    let owner = String::from_utf8_lossy(owner);
+    let owner_labels = owner
+        .trim_start_matches("*.")
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|label| !label.is_empty())
+        .count();
+
+    (labels as usize) < owner_labels
+}
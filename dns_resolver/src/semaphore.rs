@@ -0,0 +1,69 @@
+use std::{
+    error::Error,
+    sync::{Condvar, Mutex},
+    time::Instant,
+};
+
+/// Hands out at most a fixed number of permits at a time, see `Resolver::max_inflight`.
+#[derive(Debug)]
+pub(crate) struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Gives its permit back when dropped.
+pub(crate) struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub(crate) fn new(permits: usize) -> Self {
+        Self {
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits for a permit until `deadline`.
+    pub(crate) fn acquire(&self, deadline: Instant) -> Result<Permit<'_>, Box<dyn Error>> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let (mut available, _) = self
+            .released
+            .wait_timeout_while(self.available.lock().unwrap(), timeout, |available| {
+                *available == 0
+            })
+            .unwrap();
+        if *available == 0 {
+            return Err("Timed out waiting for a free query slot".into());
+        }
+
+        *available -= 1;
+        Ok(Permit { semaphore: self })
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.available.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_permits_run_out_until_one_is_dropped() {
+        let semaphore = Semaphore::new(1);
+        let deadline = || Instant::now() + Duration::from_millis(20);
+
+        let permit = semaphore.acquire(deadline()).unwrap();
+        assert!(semaphore.acquire(deadline()).is_err());
+        drop(permit);
+        assert!(semaphore.acquire(deadline()).is_ok());
+    }
+}
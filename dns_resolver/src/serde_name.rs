@@ -0,0 +1,10 @@
+//! Names are kept as raw bytes, but serialize them as readable strings.
+use serde::{Deserialize, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer>(name: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&String::from_utf8_lossy(name))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    Ok(String::deserialize(deserializer)?.into_bytes())
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    hash::Hash,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    time::Instant,
+};
+
+use crate::error::DnsError;
+
+/// Coalesces identical work running at the same time: the first caller for a key does the work,
+/// and everyone asking for the same key in the meantime waits for it and shares its result.
+#[derive(Debug)]
+pub(crate) struct SingleFlight<K, T> {
+    in_flight: Mutex<HashMap<K, Arc<Flight<T>>>>,
+}
+
+#[derive(Debug)]
+struct Flight<T> {
+    result: Mutex<Option<Result<T, SharedError>>>,
+    done: Condvar,
+}
+
+/// Lands the flight when dropped, which also happens when the work panics. Otherwise the key
+/// would stay in flight for good, with every later caller waiting out its deadline.
+struct FlightGuard<'a, K: Eq + Hash, T> {
+    single_flight: &'a SingleFlight<K, T>,
+    key: K,
+    flight: Arc<Flight<T>>,
+    result: Option<Result<T, SharedError>>,
+}
+
+impl<K: Eq + Hash, T> Drop for FlightGuard<'_, K, T> {
+    fn drop(&mut self) {
+        // Out of the map first, so that anyone asking after this point starts a new flight
+        self.single_flight
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+        let result = self.result.take().unwrap_or_else(|| {
+            Err(SharedError::Other(
+                "The same query already in flight panicked".to_string(),
+            ))
+        });
+        *self
+            .flight
+            .result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(result);
+        self.flight.done.notify_all();
+    }
+}
+
+// Errors aren't Clone, so anything but a DnsError reaches the waiting callers as its message
+#[derive(Debug, Clone)]
+enum SharedError {
+    Dns(DnsError),
+    Other(String),
+}
+
+impl<K: Eq + Hash + Clone, T: Clone> SingleFlight<K, T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `work` unless the same key is already in flight, in which case its result is
+    /// awaited until `deadline` instead.
+    pub(crate) fn run(
+        &self,
+        key: K,
+        deadline: Instant,
+        work: impl FnOnce() -> Result<T, Box<dyn Error>>,
+    ) -> Result<T, Box<dyn Error>> {
+        let mut guard = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if let Some(flight) = in_flight.get(&key) {
+                let flight = Arc::clone(flight);
+                drop(in_flight);
+                return flight.wait(deadline);
+            }
+            let flight = Arc::new(Flight {
+                result: Mutex::new(None),
+                done: Condvar::new(),
+            });
+            in_flight.insert(key.clone(), Arc::clone(&flight));
+            FlightGuard {
+                single_flight: self,
+                key,
+                flight,
+                result: None,
+            }
+        };
+
+        let result = work();
+        guard.result = Some(match &result {
+            Ok(value) => Ok(value.clone()),
+            Err(error) => Err(match error.downcast_ref::<DnsError>() {
+                Some(error) => SharedError::Dns(error.clone()),
+                None => SharedError::Other(error.to_string()),
+            }),
+        });
+        drop(guard);
+
+        result
+    }
+}
+
+impl<T: Clone> Flight<T> {
+    fn wait(&self, deadline: Instant) -> Result<T, Box<dyn Error>> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let (result, _) = self
+            .done
+            .wait_timeout_while(self.result.lock().unwrap(), timeout, |result| {
+                result.is_none()
+            })
+            .unwrap();
+
+        match result.clone() {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(SharedError::Dns(error))) => Err(error.into()),
+            Some(Err(SharedError::Other(message))) => Err(message.into()),
+            None => Err("Timed out waiting for the same query already in flight".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_panicking_work_releases_waiters() {
+        let single_flight = SingleFlight::<&str, u32>::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        thread::scope(|scope| {
+            let leader = scope.spawn(|| {
+                panic::catch_unwind(AssertUnwindSafe(|| {
+                    single_flight.run("example.com", deadline, || {
+                        thread::sleep(Duration::from_millis(100));
+                        panic!("work failed")
+                    })
+                }))
+                .is_err()
+            });
+            thread::sleep(Duration::from_millis(20));
+            let waited = single_flight.run("example.com", deadline, || Ok(1));
+
+            // The leader's panic still reaches the leader
+            assert!(leader.join().unwrap());
+            assert!(waited.is_err());
+            assert!(Instant::now() < deadline);
+        });
+
+        assert!(single_flight.in_flight.lock().unwrap().is_empty());
+        assert_eq!(
+            single_flight
+                .run("example.com", deadline, || Ok(2))
+                .unwrap(),
+            2
+        );
+    }
+}
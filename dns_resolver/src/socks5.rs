@@ -0,0 +1,112 @@
+//! Tunneling TCP connections through a SOCKS5 proxy, https://datatracker.ietf.org/doc/html/rfc1928
+
+use std::{
+    error::Error,
+    io::{Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN_NAME: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// A connection to `target` through the proxy, ready to carry whatever we would have sent to
+/// `target` directly.
+pub(crate) fn connect(
+    proxy: SocketAddr,
+    target: SocketAddr,
+    timeout: Duration,
+) -> Result<TcpStream, Box<dyn Error>> {
+    let mut stream = TcpStream::connect_timeout(&proxy, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    stream.write_all(&[VERSION, 1, NO_AUTHENTICATION])?;
+    let mut method = [0; 2];
+    stream.read_exact(&mut method)?;
+    if method != [VERSION, NO_AUTHENTICATION] {
+        return Err(format!("SOCKS5 proxy {} requires authentication", proxy).into());
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    match target.ip() {
+        IpAddr::V4(ip) => {
+            request.push(ATYP_IPV4);
+            request.extend(ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            request.push(ATYP_IPV6);
+            request.extend(ip.octets());
+        }
+    }
+    request.extend(target.port().to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != 0 {
+        return Err(format!(
+            "SOCKS5 proxy {} could not connect to {}, reply code {}",
+            proxy, target, reply[1]
+        )
+        .into());
+    }
+    // The reply ends with the address the proxy connected from, which we have no use for
+    let address_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN_NAME => {
+            let mut length = [0];
+            stream.read_exact(&mut length)?;
+            length[0] as usize
+        }
+        atyp => return Err(format!("SOCKS5 reply with unknown address type {}", atyp).into()),
+    };
+    stream.read_exact(&mut vec![0; address_len + 2])?;
+
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        net::{Ipv4Addr, TcpListener},
+        thread,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_refused_connect_is_an_error() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let proxy = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[VERSION, NO_AUTHENTICATION]).unwrap();
+            let mut request = [0; 10];
+            stream.read_exact(&mut request).unwrap();
+            // Connection refused
+            stream
+                .write_all(&[VERSION, 5, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+        });
+        let target = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 53).into(), 53);
+
+        let error = connect(proxy, target, Duration::from_millis(500)).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "SOCKS5 proxy {} could not connect to 192.0.2.53:53, reply code 5",
+                proxy
+            )
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{Read, Write},
+};
+
+use crate::{dns_packet::DNSPacket, error::DnsError, parse_options::ParseOptions};
+
+/// How queries are sent to name servers.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Transport {
+    #[default]
+    Udp,
+    /// Length-prefixed messages over a TCP connection that is kept open and reused
+    Tcp,
+    /// DNS over HTTPS to `Resolver::doh_url`, whatever server the query was meant for
+    #[cfg(feature = "doh")]
+    Doh,
+}
+
+/// A stream carrying DNS messages framed with a 2-byte length prefix, as in
+/// https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.2
+///
+/// Any `Read + Write` stream works, so TLS can be layered underneath.
+#[derive(Debug)]
+pub struct TcpConnection<S> {
+    stream: S,
+}
+
+impl<S: Read + Write> TcpConnection<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn query(
+        &mut self,
+        query: &[u8],
+        options: &ParseOptions,
+    ) -> Result<DNSPacket, Box<dyn Error>> {
+        let mut responses = self.pipeline(&[query], options)?;
+        Ok(responses.remove(0))
+    }
+
+    /// Writes every query before reading any response. Servers may answer out of order, so the
+    /// responses are matched to the queries by ID and returned in the order of the queries,
+    /// which means no two queries may share an ID.
+    pub fn pipeline(
+        &mut self,
+        queries: &[&[u8]],
+        options: &ParseOptions,
+    ) -> Result<Vec<DNSPacket>, Box<dyn Error>> {
+        let mut ids: Vec<[u8; 2]> = vec![];
+        for query in queries {
+            let id: [u8; 2] = query
+                .get(..2)
+                .ok_or_else(|| format!("Query of {} bytes is too short for an ID", query.len()))?
+                .try_into()?;
+            if ids.contains(&id) {
+                return Err(
+                    format!("Pipelined queries share the ID {}", u16::from_be_bytes(id)).into(),
+                );
+            }
+            ids.push(id);
+        }
+
+        for query in queries {
+            self.stream.write_all(&(query.len() as u16).to_be_bytes())?;
+            self.stream.write_all(query)?;
+        }
+        self.stream.flush()?;
+
+        let mut responses = HashMap::new();
+        while responses.len() < ids.len() {
+            let response = self.read_message(options)?;
+            let id: [u8; 2] = response
+                .get(..2)
+                .ok_or(DnsError::ShortResponse(response.len()))?
+                .try_into()?;
+            if ids.contains(&id) {
+                responses.insert(id, response);
+            }
+        }
+
+        ids.iter()
+            .map(|id| DNSPacket::parse_with_options(&responses[id], options))
+            .collect()
+    }
+
+    fn read_message(&mut self, options: &ParseOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut length = [0; 2];
+        self.stream.read_exact(&mut length)?;
+        let length = u16::from_be_bytes(length) as usize;
+        options.check_response_size(length)?;
+
+        let mut message = vec![0; length];
+        self.stream.read_exact(&mut message)?;
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Cursor;
+
+    use crate::{query_builder::QueryBuilder, record_type::RecordType};
+
+    use super::*;
+
+    /// Reads come from a fixed buffer, writes are collected
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn framed(message: &[u8]) -> Vec<u8> {
+        [&(message.len() as u16).to_be_bytes()[..], message].concat()
+    }
+
+    #[test]
+    fn test_pipelined_responses_are_matched_by_id() {
+        let first = QueryBuilder::new("a.example.com", RecordType::A)
+            .build()
+            .unwrap();
+        let second = QueryBuilder::new("b.example.com", RecordType::A)
+            .build()
+            .unwrap();
+        // The server echoes the queries back as responses, answering the second one first
+        let mut input = framed(&second);
+        input.extend(framed(&first));
+        let stream = MockStream {
+            input: Cursor::new(input),
+            output: vec![],
+        };
+        let mut connection = TcpConnection::new(stream);
+
+        let responses = connection
+            .pipeline(&[&first, &second], &ParseOptions::default())
+            .unwrap();
+
+        assert_eq!(responses[0].questions()[0].name(), b"a.example.com");
+        assert_eq!(responses[1].questions()[0].name(), b"b.example.com");
+        assert_eq!(
+            connection.stream.output,
+            [framed(&first), framed(&second)].concat()
+        );
+    }
+
+    #[test]
+    fn test_oversized_length_prefix_is_rejected() {
+        let query = QueryBuilder::new("example.com", RecordType::A)
+            .build()
+            .unwrap();
+        // Only the length prefix, reading a body would fail on EOF instead
+        let stream = MockStream {
+            input: Cursor::new(u16::MAX.to_be_bytes().to_vec()),
+            output: vec![],
+        };
+        let mut connection = TcpConnection::new(stream);
+        let options = ParseOptions::new().max_response_size(4096);
+
+        let error = connection.query(&query, &options).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Response of 65535 bytes exceeds the maximum of 4096 bytes"
+        );
+    }
+
+    #[test]
+    fn test_frame_too_short_for_an_id() {
+        let query = QueryBuilder::new("example.com", RecordType::A)
+            .build()
+            .unwrap();
+        let stream = MockStream {
+            input: Cursor::new(vec![0, 1, 0xab]),
+            output: vec![],
+        };
+        let mut connection = TcpConnection::new(stream);
+
+        let error = connection
+            .query(&query, &ParseOptions::default())
+            .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<DnsError>(),
+            Some(DnsError::ShortResponse(1))
+        ));
+    }
+
+    #[test]
+    fn test_unusable_queries_are_rejected_before_sending() {
+        let query = QueryBuilder::new("example.com", RecordType::A)
+            .id(7)
+            .build()
+            .unwrap();
+        let mut connection = TcpConnection::new(MockStream {
+            input: Cursor::new(vec![]),
+            output: vec![],
+        });
+
+        let short = connection
+            .pipeline(&[&query, &[0]], &ParseOptions::default())
+            .unwrap_err();
+        let shared_id = connection
+            .pipeline(&[&query, &query], &ParseOptions::default())
+            .unwrap_err();
+
+        assert_eq!(short.to_string(), "Query of 1 bytes is too short for an ID");
+        assert_eq!(shared_id.to_string(), "Pipelined queries share the ID 7");
+        assert!(connection.stream.output.is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+//! Transaction signatures as described in https://datatracker.ietf.org/doc/html/rfc8945
+
+use std::{
+    error::Error,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{
+    cursor::Cursor, encode_dns_name, parse_options::ParseOptions, record_data::RecordData,
+    record_stream::RecordStream,
+};
+
+/// The only algorithm we verify, and the one RFC 8945 requires everyone to support
+pub const HMAC_SHA256: &str = "hmac-sha256";
+// TSIG records are always of class ANY
+const CLASS_ANY: u16 = 255;
+
+pub(crate) fn parse_rdata(
+    cursor: &mut Cursor,
+    options: &ParseOptions,
+) -> Result<RecordData, Box<dyn Error>> {
+    let algorithm = cursor.read_name(options.name_decoding)?;
+    let time_signed = cursor
+        .read_bytes(6)?
+        .iter()
+        .fold(0, |acc, &byte| acc << 8 | byte as u64);
+    let fudge = cursor.read_u16()?;
+    let mac_size = cursor.read_u16()? as usize;
+    let mac = cursor.read_bytes(mac_size)?.to_vec();
+    let original_id = cursor.read_u16()?;
+    let error = cursor.read_u16()?;
+    let other_length = cursor.read_u16()? as usize;
+    let other = cursor.read_bytes(other_length)?.to_vec();
+
+    Ok(RecordData::Tsig {
+        algorithm,
+        time_signed,
+        fudge,
+        mac,
+        original_id,
+        error,
+        other,
+    })
+}
+
+/// Checks that the message ends in a TSIG record from `key_name` whose MAC matches the shared
+/// key, and that it was signed within its fudge of the current time.
+pub fn verify(message: &[u8], key_name: &str, key: &[u8]) -> Result<(), Box<dyn Error>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    verify_at(message, key_name, key, now)
+}
+
+fn verify_at(message: &[u8], key_name: &str, key: &[u8], now: u64) -> Result<(), Box<dyn Error>> {
+    let mut records = RecordStream::new(message)?;
+    while records.remaining() > 1 {
+        records.next().transpose()?;
+    }
+    let tsig_start = records.position();
+    let tsig = records.next().ok_or("Message has no TSIG record")??;
+
+    let RecordData::Tsig {
+        algorithm,
+        time_signed,
+        fudge,
+        mac,
+        original_id,
+        error,
+        other,
+    } = tsig.data()
+    else {
+        return Err("The last record of the message is not a TSIG record".into());
+    };
+    if !tsig.name().eq_ignore_ascii_case(key_name.as_bytes()) {
+        return Err("Message is signed with an unknown key".into());
+    }
+    if !algorithm.eq_ignore_ascii_case(HMAC_SHA256) {
+        return Err(format!("Unsupported TSIG algorithm {}", algorithm).into());
+    }
+
+    // The MAC covers the message as it was before the TSIG record was added, followed by the
+    // TSIG variables https://datatracker.ietf.org/doc/html/rfc8945#section-4.3.3
+    let mut signed = message[..tsig_start].to_vec();
+    signed[0..2].copy_from_slice(&original_id.to_be_bytes());
+    let additionals = u16::from_be_bytes([signed[10], signed[11]])
+        .checked_sub(1)
+        .ok_or("TSIG record is not in the additional section")?;
+    signed[10..12].copy_from_slice(&additionals.to_be_bytes());
+    signed.extend(encode_dns_name(&key_name.to_ascii_lowercase())?);
+    signed.extend(CLASS_ANY.to_be_bytes());
+    signed.extend(0u32.to_be_bytes());
+    signed.extend(encode_dns_name(&algorithm.to_ascii_lowercase())?);
+    signed.extend(&time_signed.to_be_bytes()[2..]);
+    signed.extend(fudge.to_be_bytes());
+    signed.extend(error.to_be_bytes());
+    signed.extend((other.len() as u16).to_be_bytes());
+    signed.extend(other);
+
+    let mut hmac = Hmac::<Sha256>::new_from_slice(key)?;
+    hmac.update(&signed);
+    hmac.verify_slice(mac)
+        .map_err(|_| "TSIG MAC does not match the message")?;
+
+    if now.abs_diff(*time_signed) > *fudge as u64 {
+        return Err(format!(
+            "TSIG was signed at {}, more than {} seconds from now",
+            time_signed, fudge
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        dns_packet::DNSPacket, dns_record::DNSRecord, query_builder::QueryBuilder,
+        record_type::RecordType,
+    };
+
+    use super::*;
+
+    const KEY: &[u8] = b"secret-key-for-tests";
+    const TIME_SIGNED: u64 = 1_700_000_000;
+
+    /// A query for example.com signed with KEY, its MAC computed independently with Python's hmac
+    fn signed_query() -> Vec<u8> {
+        let mut message = QueryBuilder::new("example.com", RecordType::A)
+            .id(0x1234)
+            .without_edns()
+            .build()
+            .unwrap();
+        message[11] = 1;
+        let tsig = RecordData::Tsig {
+            algorithm: HMAC_SHA256.to_string(),
+            time_signed: TIME_SIGNED,
+            fudge: 300,
+            mac: vec![
+                0xa5, 0x1f, 0x57, 0x15, 0xbd, 0x46, 0x2d, 0x90, 0x71, 0x4b, 0x31, 0x7d, 0x9c, 0xa0,
+                0x2c, 0xde, 0x3a, 0xc9, 0x13, 0x14, 0x85, 0xe2, 0x81, 0x87, 0xfe, 0xa4, 0x5a, 0xd4,
+                0xdd, 0x4d, 0x32, 0xaa,
+            ],
+            original_id: 0x1234,
+            error: 0,
+            other: vec![],
+        };
+        message.extend(
+            DNSRecord::new(b"test-key".to_vec(), RecordType::TSIG, CLASS_ANY, 0, tsig)
+                .unwrap()
+                .to_bytes()
+                .unwrap(),
+        );
+        message
+    }
+
+    #[test]
+    fn test_parse_tsig() {
+        let packet = DNSPacket::try_from(&signed_query()[..]).unwrap();
+
+        let tsig = &packet.additionals()[0];
+        assert_eq!(tsig.type_(), RecordType::TSIG);
+        let RecordData::Tsig {
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            ..
+        } = tsig.data()
+        else {
+            panic!("Expected a TSIG record");
+        };
+        assert_eq!(algorithm, HMAC_SHA256);
+        assert_eq!(*time_signed, TIME_SIGNED);
+        assert_eq!(*fudge, 300);
+        assert_eq!(mac.len(), 32);
+    }
+
+    #[test]
+    fn test_verify_tsig() {
+        let message = signed_query();
+
+        assert!(verify_at(&message, "test-key", KEY, TIME_SIGNED + 10).is_ok());
+        assert!(verify_at(&message, "test-key", b"wrong-key", TIME_SIGNED).is_err());
+        assert!(verify_at(&message, "test-key", KEY, TIME_SIGNED + 301).is_err());
+    }
+
+    #[test]
+    fn test_tampered_message_fails_verification() {
+        let mut message = signed_query();
+        // Ask for AAAA instead
+        message[12 + 13 + 1] = 28;
+
+        assert!(verify_at(&message, "test-key", KEY, TIME_SIGNED).is_err());
+    }
+}